@@ -1,12 +1,15 @@
 use std::{fs::File, io::{Cursor, Read, Write}};
 
 use clap::Parser;
-use lmers::{external::obabel::obabel, io::BasicIOMolecule, layer::{Layer, SelectOne}, sparse_molecule::SparseMolecule, utils::sterimol::{self, auto_connect_bonds, get_molecular_graph, RadiisTable}};
+use lmers::{external::obabel::obabel, io::BasicIOMolecule, layer::{Layer, SelectMany, SelectOne}, sparse_molecule::SparseMolecule, utils::sterimol::{self, auto_connect_bonds, get_molecular_graph, Radiis, RadiisTable}, elements::RadiiSet};
 use nalgebra::Vector3;
 use rayon::prelude::*;
 use glob::glob;
 use anyhow::{anyhow, Context, Result};
 
+/// Formats read and written without OpenBabel
+const NATIVE_FORMATS: [&str; 5] = ["mol", "sdf", "pdb", "cif", "mmcif"];
+
 #[derive(Parser)]
 enum Operation {
     /// Import commom molecular files to LME
@@ -23,9 +26,12 @@ enum Operation {
         /// Prepare generate file as substituents
         #[clap(short='s')]
         as_substituent: bool,
-        /// Specify the pathway of radius table for generate sterimol descriptors
-        #[clap(short='S')]
-        sterimol: Option<String>
+        /// Perceive bonds and formal charges from coordinates natively instead of OpenBabel, XYZ input only
+        #[clap(short='p')]
+        perceive_bonds: bool,
+        /// Generate sterimol descriptors, with the built-in radii or the radius table in given path
+        #[clap(short='S', num_args=0..=1)]
+        sterimol: Option<Option<String>>
     },
     /// Export LME files to common formats
     Export {
@@ -35,13 +41,19 @@ enum Operation {
         /// Output file format
         #[clap(short)]
         output_format: String,
+        /// Write all structures as frames of one file at the given path, xyz, extxyz and sdf only
+        #[clap(short)]
+        merge: Option<String>,
     }
 }
 
 impl Operation {
     fn operate(self) -> Result<()> {
         match self {
-            Self::Import { input_filepath, input_format, gen3d, as_substituent, sterimol } => {
+            Self::Import { input_filepath, input_format, gen3d, as_substituent, perceive_bonds, sterimol } => {
+                if perceive_bonds && input_format != "xyz" {
+                    Err(anyhow!("Bond perception is only available for XYZ input, got {}", input_format))?
+                }
                 let matched_paths = glob(&input_filepath).with_context(|| format!("Invalid file match pattern: {}", input_filepath))?;
                 let set_center_layer = Layer::SetCenter {
                     select: SelectOne::Index(0),
@@ -51,12 +63,16 @@ impl Operation {
                     select: SelectOne::Index(1),
                     direction: Vector3::x(),
                 };
-                let radiis_table = if let Some(radiis_path) = sterimol {
-                    let file = File::open(&radiis_path).with_context(|| format!("Failed to open speicified radiis table {}", radiis_path))?;
-                    let table: RadiisTable = serde_json::from_reader(file).with_context(|| "Unable to parse given radiis table")?;
-                    Some(table)
-                } else {
-                    None
+                // The given radius table is used for both bonds and sterimol descriptors as before,
+                // otherwise covalent radii for bonds and van der Waals radii for descriptors.
+                let radiis_table = match sterimol {
+                    Some(Some(radiis_path)) => {
+                        let file = File::open(&radiis_path).with_context(|| format!("Failed to open speicified radiis table {}", radiis_path))?;
+                        let table: RadiisTable = serde_json::from_reader(file).with_context(|| "Unable to parse given radiis table")?;
+                        Some((Radiis::Table(table.clone()), Radiis::Table(table)))
+                    },
+                    Some(None) => Some((Radiis::Builtin(RadiiSet::Covalent), Radiis::Builtin(RadiiSet::Vdw))),
+                    None => None,
                 };
                 let _ = matched_paths.par_bridge()
                     .map(|entry| {
@@ -65,42 +81,79 @@ impl Operation {
                         File::open(&input).with_context(|| format!("Failed to open matched file {:?}", input))?
                             .read_to_string(&mut input_content)
                             .with_context(|| format!("Failed to read matched file {:?}", input))?;
-                        let mol2 = obabel(&input_content, &input_format, "mol2", true, gen3d)?;
-                        let mut molecule = SparseMolecule::from(BasicIOMolecule::input("mol2", Cursor::new(mol2))?);
-                        if as_substituent {
-                            molecule = align_layer.filter(set_center_layer.filter(molecule).map_err(|_| anyhow!("Substituent require at least 2 atoms"))?).map_err(|_| anyhow!("Substituent require at least 2 atoms"))?;
-                        }
-                        input.set_extension("lme");
-                        serde_json::to_writer(File::create(&input).with_context(|| format!("Unable to create output file at {:?}", input))?, &molecule)?;
-                        if let Some(radiis_table) = &radiis_table {
-                            let bonds = molecule.bonds.to_continuous_list(&molecule.atoms);
-                            let atoms = molecule.atoms.into();
-                            let bonds = if bonds.len() == 0 {
-                                auto_connect_bonds(&atoms, radiis_table)?
-                            } else {
-                                bonds
-                            };
-                            let molecular_graph = get_molecular_graph(&atoms, &bonds);
-                            let (l, b1, b5) = sterimol::sterimol(&molecular_graph, radiis_table)?;
-                            let tca = sterimol::tolman_cone_angle(&molecular_graph)?;
-                            input.set_extension("sterimol");
-                            File::create(&input).with_context(|| format!("Unable to create sterimol file at {:?}", input))?
-                                .write_all(format!("{l},{b1},{b5},{tca}").as_bytes())
-                                .with_context(|| format!("Unable to write sterimol file at {:?}", input))?;
+                        // MOL, SDF, PDB and mmCIF files are read natively unless 3D structures are to be generated,
+                        // each frame of XYZ files, record of SDF files or model of PDB files is imported as a separate structure
+                        let molecules = if perceive_bonds {
+                            BasicIOMolecule::input_all("xyz", Cursor::new(input_content))?.into_iter()
+                                .map(|molecule| Layer::PerceiveBonds { select: SelectMany::All }.filter(SparseMolecule::from(molecule)).map_err(|err| anyhow!("Failed to perceive bonds: {}", err)))
+                                .collect::<Result<Vec<_>>>()?
+                        } else if !gen3d && NATIVE_FORMATS.contains(&input_format.as_str()) {
+                            BasicIOMolecule::input_all(&input_format, Cursor::new(input_content)).with_context(|| format!("Failed to read matched file {:?}", input))?
+                                .into_iter().map(SparseMolecule::from).collect()
+                        } else {
+                            let mol2 = obabel(&input_content, &input_format, "mol2", true, gen3d)?;
+                            vec![SparseMolecule::from(BasicIOMolecule::input("mol2", Cursor::new(mol2))?)]
+                        };
+                        let multiple = molecules.len() > 1;
+                        let stem = input.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+                        for (index, mut molecule) in molecules.into_iter().enumerate() {
+                            if multiple {
+                                input.set_file_name(format!("{}_{}", stem, index + 1));
+                            }
+                            if as_substituent {
+                                molecule = align_layer.filter(set_center_layer.filter(molecule).map_err(|_| anyhow!("Substituent require at least 2 atoms"))?).map_err(|_| anyhow!("Substituent require at least 2 atoms"))?;
+                            }
+                            input.set_extension("lme");
+                            serde_json::to_writer(File::create(&input).with_context(|| format!("Unable to create output file at {:?}", input))?, &molecule)?;
+                            if let Some((bond_radiis, radiis_table)) = &radiis_table {
+                                let bonds = molecule.bonds.to_continuous_list(&molecule.atoms);
+                                let atoms = molecule.atoms.into();
+                                let bonds = if bonds.len() == 0 {
+                                    auto_connect_bonds(&atoms, bond_radiis)?
+                                } else {
+                                    bonds
+                                };
+                                let molecular_graph = get_molecular_graph(&atoms, &bonds);
+                                let (l, b1, b5) = sterimol::sterimol(&molecular_graph, radiis_table)?;
+                                let tca = sterimol::tolman_cone_angle(&molecular_graph)?;
+                                input.set_extension("sterimol");
+                                File::create(&input).with_context(|| format!("Unable to create sterimol file at {:?}", input))?
+                                    .write_all(format!("{l},{b1},{b5},{tca}").as_bytes())
+                                    .with_context(|| format!("Unable to write sterimol file at {:?}", input))?;
+                            }
                         }
                         Ok(())
                     })
                     .collect::<Result<Vec<()>>>()?;
                 Ok(())
             },
-            Self::Export { input_filepath, output_format } => {
+            Self::Export { input_filepath, output_format, merge } => {
                 let matched_paths = glob(&input_filepath).with_context(|| format!("Invalid file match pattern: {}", input_filepath))?;
+                if let Some(merge) = merge {
+                    let frames = matched_paths
+                        .map(|entry| {
+                            let input = entry.with_context(|| format!("Unable to read path matched"))?;
+                            let structure: SparseMolecule = serde_yaml::from_reader(File::open(&input).with_context(|| format!("Failed to open matched file {:?}", input))?)?;
+                            Ok(BasicIOMolecule::from((structure, input.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default())))
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+                    let output = BasicIOMolecule::output_all(&frames, &output_format)?;
+                    File::create(&merge).with_context(|| format!("Failed to create output file {:?}", merge))?
+                        .write_all(output.as_bytes())
+                        .with_context(|| format!("Failed to write to output file {:?}", merge))?;
+                    return Ok(());
+                }
                 let _ = matched_paths.par_bridge()
                     .map(|entry| {
                         let mut input = entry.with_context(|| format!("Unable to read path matched"))?;
                         let structure: SparseMolecule = serde_yaml::from_reader(File::open(&input).with_context(|| format!("Failed to open matched file {:?}", input))?)?;
-                        let mol2 = BasicIOMolecule::from((structure, input.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default())).output("mol2").with_context(|| format!("Failed to convert to intermediate format {:?}", input))?;
-                        let output = obabel(&mol2, "mol2", &output_format, true, false)?;
+                        let molecule = BasicIOMolecule::from((structure, input.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default()));
+                        let output = if NATIVE_FORMATS.contains(&output_format.as_str()) {
+                            molecule.output(&output_format).with_context(|| format!("Failed to convert {:?}", input))?
+                        } else {
+                            let mol2 = molecule.output("mol2").with_context(|| format!("Failed to convert to intermediate format {:?}", input))?;
+                            obabel(&mol2, "mol2", &output_format, true, false)?
+                        };
                         input.set_extension(output_format.clone());
                         File::create(&input).with_context(|| format!("Failed to create output file {:?}", input))?
                             .write_all(output.as_bytes())
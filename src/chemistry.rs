@@ -1,8 +1,18 @@
-use bincode::{Decode, Encode};
+use bincode::{
+    de::Decoder,
+    enc::Encoder,
+    error::{DecodeError, EncodeError},
+    impl_borrow_decode,
+    serde::Compat,
+    Decode, Encode,
+};
 use lazy_static::lazy_static;
 use nalgebra::Point3;
 use serde::{Deserialize, Serialize};
-use std::{borrow::Borrow, collections::BTreeSet};
+use std::{
+    borrow::Borrow,
+    collections::{BTreeMap, BTreeSet},
+};
 
 lazy_static! {
     static ref ELEMENT_SET: BTreeSet<(usize, &'static str)> = BTreeSet::from([
@@ -154,11 +164,216 @@ pub fn element_symbol_to_num(input: &str) -> Option<usize> {
     })
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, Encode, Decode)]
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(from = "Atom3DLoader")]
 pub struct Atom3D {
     pub element: usize,
-    #[bincode(with_serde)]
     pub position: Point3<f64>,
     #[serde(default)]
-    pub formal_charge: f64
+    pub formal_charge: f64,
+    /// Partial charge from charge models or input files, kept apart from the formal charge
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub partial_charge: Option<f64>,
+    /// Atom name like `CA` in PDB or `C1` in mol2 files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub residue_id: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub residue_name: Option<String>,
+    /// Chain identifier like `A` in PDB files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chain: Option<String>,
+    /// Mass number of the isotope, `None` for natural abundance
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub isotope: Option<usize>,
+    /// User defined key-value attributes
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+    /// Hidden atoms are kept in the structure but skipped when exporting
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub hidden: bool,
+}
+
+/// Hidden atoms were stored with the element number plus 128 in previous versions, they are
+/// loaded with the `hidden` flag instead.
+#[derive(Deserialize)]
+struct Atom3DLoader {
+    element: usize,
+    position: Point3<f64>,
+    #[serde(default)]
+    formal_charge: f64,
+    #[serde(default)]
+    partial_charge: Option<f64>,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    residue_id: Option<i64>,
+    #[serde(default)]
+    residue_name: Option<String>,
+    #[serde(default)]
+    chain: Option<String>,
+    #[serde(default)]
+    isotope: Option<usize>,
+    #[serde(default)]
+    tags: BTreeMap<String, String>,
+    #[serde(default)]
+    hidden: bool,
+}
+
+impl From<Atom3DLoader> for Atom3D {
+    fn from(value: Atom3DLoader) -> Self {
+        Self {
+            element: value.element,
+            position: value.position,
+            formal_charge: value.formal_charge,
+            partial_charge: value.partial_charge,
+            name: value.name,
+            residue_id: value.residue_id,
+            residue_name: value.residue_name,
+            chain: value.chain,
+            isotope: value.isotope,
+            tags: value.tags,
+            hidden: value.hidden,
+        }
+        .with_legacy_hidden()
+    }
+}
+
+impl Atom3D {
+    /// Move the legacy hidden mark, 128 added to the element number by each `Hide` layer,
+    /// to the `hidden` flag.
+    fn with_legacy_hidden(mut self) -> Self {
+        while self.element > 128 && !validated_element_num(self.element) {
+            self.element -= 128;
+            self.hidden = true;
+        }
+        self
+    }
+
+    /// Whether the atom exists in the structure, atoms removed by `RemoveAtoms`
+    /// layers are stored with an invalid element number.
+    pub fn is_present(&self) -> bool {
+        validated_element_num(self.element)
+    }
+
+    /// Whether the atom exists and is not hidden.
+    pub fn is_visible(&self) -> bool {
+        self.is_present() && !self.hidden
+    }
+
+    /// Take the attributes from `other` if they are set, the current ones are kept otherwise.
+    pub fn merge_attributes(&mut self, other: &Self) {
+        self.partial_charge = other.partial_charge.or(self.partial_charge);
+        self.name = other.name.clone().or(self.name.take());
+        self.residue_id = other.residue_id.or(self.residue_id);
+        self.residue_name = other.residue_name.clone().or(self.residue_name.take());
+        self.chain = other.chain.clone().or(self.chain.take());
+        self.isotope = other.isotope.or(self.isotope);
+        self.tags.extend(other.tags.clone());
+    }
+}
+
+/// Leading value of the versioned binary form of `Atom3D`, followed by the version. The
+/// baseline form starts with the element number directly, which never reaches it.
+const ATOM3D_VERSION_TAG: u64 = u16::MAX as u64;
+const ATOM3D_VERSION: u8 = 1;
+
+impl Encode for Atom3D {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        ATOM3D_VERSION_TAG.encode(encoder)?;
+        ATOM3D_VERSION.encode(encoder)?;
+        self.element.encode(encoder)?;
+        Compat(self.position).encode(encoder)?;
+        self.formal_charge.encode(encoder)?;
+        self.partial_charge.encode(encoder)?;
+        self.name.encode(encoder)?;
+        self.residue_id.encode(encoder)?;
+        self.residue_name.encode(encoder)?;
+        self.isotope.encode(encoder)?;
+        self.tags.encode(encoder)?;
+        self.hidden.encode(encoder)?;
+        self.chain.encode(encoder)
+    }
+}
+
+impl Decode for Atom3D {
+    fn decode<D: Decoder>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let leading = u64::decode(decoder)?;
+        if leading != ATOM3D_VERSION_TAG {
+            return Ok(Self {
+                element: usize::try_from(leading)
+                    .map_err(|_| DecodeError::OutsideUsizeRange(leading))?,
+                position: Compat::decode(decoder)?.0,
+                formal_charge: Decode::decode(decoder)?,
+                ..Default::default()
+            }
+            .with_legacy_hidden());
+        }
+        match u8::decode(decoder)? {
+            ATOM3D_VERSION => Ok(Self {
+                element: Decode::decode(decoder)?,
+                position: Compat::decode(decoder)?.0,
+                formal_charge: Decode::decode(decoder)?,
+                partial_charge: Decode::decode(decoder)?,
+                name: Decode::decode(decoder)?,
+                residue_id: Decode::decode(decoder)?,
+                residue_name: Decode::decode(decoder)?,
+                isotope: Decode::decode(decoder)?,
+                tags: Decode::decode(decoder)?,
+                hidden: Decode::decode(decoder)?,
+                chain: Decode::decode(decoder)?,
+            }),
+            version => Err(DecodeError::OtherString(format!(
+                "Unknown Atom3D encoding version {version}"
+            ))),
+        }
+    }
+}
+
+impl_borrow_decode!(Atom3D);
+
+#[test]
+fn decode_atom_without_attributes() {
+    #[derive(Encode)]
+    struct LegacyAtom3D {
+        element: usize,
+        #[bincode(with_serde)]
+        position: Point3<f64>,
+        formal_charge: f64,
+    }
+    let legacy = LegacyAtom3D {
+        element: 8,
+        position: Point3::new(1., 2., 3.),
+        formal_charge: -1.,
+    };
+    let encoded = bincode::encode_to_vec(legacy, bincode::config::standard()).unwrap();
+    let (atom, _): (Atom3D, _) =
+        bincode::decode_from_slice(&encoded, bincode::config::standard()).unwrap();
+    assert_eq!(atom.element, 8);
+    assert_eq!(atom.position, Point3::new(1., 2., 3.));
+    assert_eq!(atom.formal_charge, -1.);
+    assert_eq!(atom.name, None);
+    let hidden = LegacyAtom3D {
+        element: 6 + 128,
+        position: Point3::origin(),
+        formal_charge: 0.,
+    };
+    let encoded = bincode::encode_to_vec(hidden, bincode::config::standard()).unwrap();
+    let (hidden, _): (Atom3D, _) =
+        bincode::decode_from_slice(&encoded, bincode::config::standard()).unwrap();
+    assert_eq!((hidden.element, hidden.hidden), (6, true));
+    let hidden: Atom3D =
+        serde_json::from_str(r#"{"element":136,"position":[0,0,0],"formal_charge":0}"#).unwrap();
+    assert_eq!((hidden.element, hidden.hidden), (8, true));
+    let atom = Atom3D {
+        name: Some("O1".to_string()),
+        chain: Some("A".to_string()),
+        tags: BTreeMap::from([("layer".to_string(), "QM".to_string())]),
+        ..atom
+    };
+    let encoded = bincode::encode_to_vec(&atom, bincode::config::standard()).unwrap();
+    let (decoded, _): (Atom3D, _) =
+        bincode::decode_from_slice(&encoded, bincode::config::standard()).unwrap();
+    assert_eq!(decoded, atom);
 }
@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+
+use nalgebra::{Matrix3, Point3, Vector3};
+use serde::Serialize;
+
+use crate::{
+    chemistry::{element_num_to_symbol, Atom3D},
+    elements::atomic_mass,
+    layer::SelectMany,
+    sparse_molecule::SparseMolecule,
+};
+
+/// Mass of the atom in g/mol, the mass number is used for atoms with isotope specified
+fn atom_mass(atom: &Atom3D) -> f64 {
+    atom.isotope
+        .map(|isotope| isotope as f64)
+        .or_else(|| atomic_mass(atom.element))
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Descriptors {
+    pub formula: String,
+    pub atoms: usize,
+    pub mass: f64,
+    pub centroid: Option<[f64; 3]>,
+    pub center_of_mass: Option<[f64; 3]>,
+    /// Principal moments of inertia in amu·Å², in ascending order
+    pub principal_moments: Option<[f64; 3]>,
+}
+
+impl SparseMolecule {
+    /// Atoms in the selection, atoms removed by `RemoveAtoms` layers are skipped
+    pub fn selected_atoms(&self, select: &SelectMany) -> Vec<Atom3D> {
+        select
+            .to_indexes(self)
+            .into_iter()
+            .filter_map(|index| self.atoms.read_atom(index))
+            .filter(|atom| atom.is_present())
+            .collect()
+    }
+
+    /// Molecular formula in Hill order, carbon and hydrogen first if carbon exists,
+    /// other elements in alphabetical order
+    pub fn formula(&self, select: &SelectMany) -> String {
+        let mut counts = BTreeMap::new();
+        for atom in self.selected_atoms(select) {
+            if let Some(symbol) = element_num_to_symbol(atom.element) {
+                *counts.entry(symbol).or_insert(0usize) += 1;
+            }
+        }
+        let mut formula = String::new();
+        let mut push = |symbol: &str, count: usize| {
+            formula.push_str(symbol);
+            if count > 1 {
+                formula.push_str(&count.to_string());
+            }
+        };
+        if let Some(carbon) = counts.remove("C") {
+            push("C", carbon);
+            if let Some(hydrogen) = counts.remove("H") {
+                push("H", hydrogen);
+            }
+        }
+        for (symbol, count) in counts {
+            push(symbol, count);
+        }
+        formula
+    }
+
+    pub fn molecular_mass(&self, select: &SelectMany) -> f64 {
+        self.selected_atoms(select).iter().map(atom_mass).sum()
+    }
+
+    pub fn centroid(&self, select: &SelectMany) -> Option<Point3<f64>> {
+        let atoms = self.selected_atoms(select);
+        if atoms.is_empty() {
+            return None;
+        }
+        let sum = atoms
+            .iter()
+            .fold(Vector3::zeros(), |acc, atom| acc + atom.position.coords);
+        Some(Point3::from(sum / atoms.len() as f64))
+    }
+
+    pub fn center_of_mass(&self, select: &SelectMany) -> Option<Point3<f64>> {
+        let atoms = self.selected_atoms(select);
+        let mass = atoms.iter().map(atom_mass).sum::<f64>();
+        if mass == 0. {
+            return None;
+        }
+        let sum = atoms.iter().fold(Vector3::zeros(), |acc, atom| {
+            acc + atom.position.coords * atom_mass(atom)
+        });
+        Some(Point3::from(sum / mass))
+    }
+
+    /// Inertia tensor about the center of mass in amu·Å²
+    pub fn inertia_tensor(&self, select: &SelectMany) -> Option<Matrix3<f64>> {
+        let center = self.center_of_mass(select)?;
+        let tensor = self
+            .selected_atoms(select)
+            .iter()
+            .fold(Matrix3::zeros(), |acc, atom| {
+                let r = atom.position - center;
+                acc + (Matrix3::identity() * r.norm_squared() - r * r.transpose()) * atom_mass(atom)
+            });
+        Some(tensor)
+    }
+
+    /// Principal moments of inertia in ascending order, and the principal axes as
+    /// columns of the matrix in the same order
+    pub fn principal_axes(&self, select: &SelectMany) -> Option<(Vector3<f64>, Matrix3<f64>)> {
+        let eigen = self.inertia_tensor(select)?.symmetric_eigen();
+        let mut order = [0, 1, 2];
+        order.sort_by(|a, b| eigen.eigenvalues[*a].total_cmp(&eigen.eigenvalues[*b]));
+        let moments = Vector3::from_fn(|i, _| eigen.eigenvalues[order[i]]);
+        let axes = Matrix3::from_columns(&order.map(|i| eigen.eigenvectors.column(i).into_owned()));
+        Some((moments, axes))
+    }
+
+    pub fn descriptors(&self, select: &SelectMany) -> Descriptors {
+        Descriptors {
+            formula: self.formula(select),
+            atoms: self.selected_atoms(select).len(),
+            mass: self.molecular_mass(select),
+            centroid: self.centroid(select).map(|point| point.coords.into()),
+            center_of_mass: self.center_of_mass(select).map(|point| point.coords.into()),
+            principal_moments: self
+                .principal_axes(select)
+                .map(|(moments, _)| moments.into()),
+        }
+    }
+}
+
+#[test]
+fn water_descriptors() {
+    let molecule = SparseMolecule {
+        atoms: crate::sparse_molecule::SparseAtomList::from(vec![
+            Atom3D {
+                element: 8,
+                ..Default::default()
+            },
+            Atom3D {
+                element: 1,
+                position: Point3::new(0.757, 0.586, 0.),
+                ..Default::default()
+            },
+            Atom3D {
+                element: 1,
+                position: Point3::new(-0.757, 0.586, 0.),
+                ..Default::default()
+            },
+        ]),
+        ..Default::default()
+    };
+    let descriptors = molecule.descriptors(&SelectMany::All);
+    assert_eq!(descriptors.formula, "H2O");
+    assert!((descriptors.mass - 18.015).abs() < 1e-10);
+    let center = descriptors.center_of_mass.unwrap();
+    assert!(center[0].abs() < 1e-10 && center[1] > 0. && center[1] < 0.586 / 3.);
+    let (moments, axes) = molecule.principal_axes(&SelectMany::All).unwrap();
+    assert!(moments[0] < moments[1] && moments[1] < moments[2]);
+    assert!((axes.column(2).into_owned().cross(&Vector3::z())).norm() < 1e-10);
+    assert_eq!(molecule.formula(&SelectMany::Element(1)), "H2");
+}
@@ -5,10 +5,12 @@ use std::{
 
 use crate::{
     chemistry::{element_num_to_symbol, element_symbol_to_num, Atom3D},
+    group_name::GroupName,
+    lattice::Lattice,
     sparse_molecule::{SparseAtomList, SparseBondMatrix, SparseMolecule},
 };
 use anyhow::{anyhow, Context, Error, Result};
-use nalgebra::Point3;
+use nalgebra::{Point3, Vector3};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 
@@ -22,7 +24,14 @@ pub struct NamespaceMapping {
 
 impl From<SparseMolecule> for NamespaceMapping {
     fn from(value: SparseMolecule) -> Self {
-        let atoms_mapping: BTreeMap<usize, usize> = value.atoms.into();
+        Self::new(value, false)
+    }
+}
+
+impl NamespaceMapping {
+    /// Create the mapping of exported atoms, hidden atoms are exported if `with_hidden` is set.
+    pub fn new(value: SparseMolecule, with_hidden: bool) -> Self {
+        let atoms_mapping = value.atoms.continuous_indexes(with_hidden);
         let ids = value
             .ids
             .map(|ids| {
@@ -68,6 +77,121 @@ pub struct BasicIOMolecule {
     pub atoms: Vec<Atom3D>,
     pub bonds: Vec<(usize, usize, f64)>,
     pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lattice: Option<Lattice>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub charge: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub multiplicity: Option<usize>,
+    /// Ids of atoms read from formats like PDB, kept as ids of the imported structure
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub ids: BTreeMap<String, usize>,
+    /// Groups of atoms read from formats like PDB, kept as groups of the imported structure
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub groups: BTreeMap<String, BTreeSet<usize>>,
+    /// Scalar results like energies read from calculation outputs, in atomic units
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub properties: BTreeMap<String, f64>,
+}
+
+/// A structure in files with multiple structures like trajectories and optimization logs,
+/// loaded from `first`, `last`, `lowest_energy`, `all` or an index counted from 0
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(try_from = "FrameLoader")]
+pub enum Frame {
+    First,
+    #[default]
+    Last,
+    Index(usize),
+    /// The structure with the lowest `energy` or `scf_energy` property
+    LowestEnergy,
+    /// Every structure as a separate one, only for readers taking many structures
+    All,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FrameLoader {
+    Index(usize),
+    Name(String),
+}
+
+impl TryFrom<FrameLoader> for Frame {
+    type Error = anyhow::Error;
+    fn try_from(value: FrameLoader) -> Result<Self> {
+        match value {
+            FrameLoader::Index(index) => Ok(Self::Index(index)),
+            FrameLoader::Name(name) => match name.as_str() {
+                "first" => Ok(Self::First),
+                "last" => Ok(Self::Last),
+                "lowest_energy" => Ok(Self::LowestEnergy),
+                "all" => Ok(Self::All),
+                name => Err(anyhow!(
+                    "Unknown frame {name}, expected first, last, lowest_energy, all or an index"
+                )),
+            },
+        }
+    }
+}
+
+impl Frame {
+    pub fn select(&self, mut frames: Vec<BasicIOMolecule>) -> Result<BasicIOMolecule> {
+        let count = frames.len();
+        let index = match self {
+            Self::First => 0,
+            Self::Last => count.checked_sub(1).with_context(|| "No structure found")?,
+            Self::Index(index) => *index,
+            Self::LowestEnergy => frames
+                .iter()
+                .enumerate()
+                .filter_map(|(index, frame)| {
+                    let energy = frame.properties.get("energy");
+                    Some((index, *energy.or(frame.properties.get("scf_energy"))?))
+                })
+                .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                .map(|(index, _)| index)
+                .with_context(|| "No energy found in the structures")?,
+            Self::All => Err(anyhow!("Unable to select all structures as one"))?,
+        };
+        if index < count {
+            Ok(frames.swap_remove(index))
+        } else {
+            Err(anyhow!("Frame {index} not found in {count} structures"))
+        }
+    }
+}
+
+/// Options of Gaussian input files written with the `gaussian` format
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct GaussianOptions {
+    /// Link 0 commands like `%nprocshared=16` and `%mem=32GB`
+    #[serde(default)]
+    pub link0: Vec<String>,
+    /// Route section like `#p opt=modredundant b3lyp/def2svp`, `#p sp` if omitted
+    #[serde(default)]
+    pub route: String,
+    /// Frozen coordinates of the ModRedundant section, each has 1 to 4 atoms by ids or indexes
+    /// of exported atoms, for cartesian positions, bonds, angles or dihedrals
+    #[serde(default)]
+    pub constraints: Vec<Vec<String>>,
+    /// Route sections of following jobs after `--Link1--`, sharing the checkpoint file
+    #[serde(default)]
+    pub link1: Vec<String>,
+}
+
+/// Options of ORCA input files written with the `orca` format
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct OrcaOptions {
+    /// Simple input line like `! Opt B3LYP def2-SVP`, `! SP` if omitted
+    #[serde(default)]
+    pub keywords: String,
+    /// Input blocks like `%pal nprocs 16 end` and `%maxcore 4000`
+    #[serde(default)]
+    pub blocks: Vec<String>,
+    /// Frozen coordinates of the `%geom Constraints` block, each has 1 to 4 atoms by ids or
+    /// indexes of exported atoms, for cartesian positions, bonds, angles or dihedrals
+    #[serde(default)]
+    pub constraints: Vec<Vec<String>>,
 }
 
 impl From<BasicIOMolecule> for SparseMolecule {
@@ -77,11 +201,22 @@ impl From<BasicIOMolecule> for SparseMolecule {
         for (a, b, bond) in value.bonds {
             bonds.set_bond(a, b, Some(bond));
         }
+        let groups = (!value.groups.is_empty()).then(|| {
+            let mut groups = GroupName::new();
+            for (name, indexes) in value.groups {
+                groups.insert_left(name, indexes.into_iter());
+            }
+            groups
+        });
         Self {
             atoms,
             bonds,
-            ids: None,
-            groups: None,
+            ids: (!value.ids.is_empty()).then_some(value.ids),
+            groups,
+            lattice: value.lattice,
+            charge: value.charge,
+            multiplicity: value.multiplicity,
+            properties: value.properties,
         }
     }
 }
@@ -89,10 +224,17 @@ impl From<BasicIOMolecule> for SparseMolecule {
 impl From<(SparseMolecule, String)> for BasicIOMolecule {
     fn from((molecule, title): (SparseMolecule, String)) -> Self {
         let bonds = molecule.bonds.to_continuous_list(&molecule.atoms);
+        let (charge, multiplicity) = (molecule.total_charge(), molecule.spin_multiplicity());
         Self {
             atoms: molecule.atoms.into(),
             bonds,
             title,
+            lattice: molecule.lattice,
+            charge: Some(charge),
+            multiplicity: Some(multiplicity),
+            ids: BTreeMap::new(),
+            groups: BTreeMap::new(),
+            properties: molecule.properties,
         }
     }
 }
@@ -103,94 +245,193 @@ impl BasicIOMolecule {
             title,
             atoms,
             bonds,
+            lattice: None,
+            charge: None,
+            multiplicity: None,
+            ids: BTreeMap::new(),
+            groups: BTreeMap::new(),
+            properties: BTreeMap::new(),
         }
     }
 
     pub fn output(&self, format: &str) -> Result<String> {
         match format {
-            "xyz" => self.output_to_xyz(),
+            "xyz" => self.output_to_xyz(false),
+            "extxyz" => self.output_to_xyz(true),
             "mol2" => self.output_to_mol2(),
+            "gaussian" => self.output_to_gaussian(&Default::default()),
+            "orca" => self.output_to_orca(&Default::default()),
+            "pdb" => self.output_to_pdb(),
+            "cif" | "mmcif" => self.output_to_cif(),
+            "mol" => self.output_to_mol(false),
+            "mol_v3000" => self.output_to_mol(true),
+            "sdf" => Ok(format!(
+                "{}\n{}$$$$",
+                self.output_to_mol(false)?,
+                self.sdf_data()
+            )),
+            "sdf_v3000" => Ok(format!(
+                "{}\n{}$$$$",
+                self.output_to_mol(true)?,
+                self.sdf_data()
+            )),
+            "poscar" => self.output_to_poscar(),
+            "cp2k" => self.output_to_cp2k(),
             "lme_json" => Ok(serde_json::to_string(&self)?),
             "nothing" => Ok(String::from("")),
             format => Err(anyhow!("Unsupported format {format}")),
         }
     }
 
+    /// Write structures as frames of one file, like trajectories of XYZ files and records of
+    /// SDF files
+    pub fn output_all(frames: &[Self], format: &str) -> Result<String> {
+        match format {
+            "xyz" | "extxyz" | "sdf" | "sdf_v3000" => Ok(frames
+                .iter()
+                .map(|frame| frame.output(format))
+                .collect::<Result<Vec<_>>>()?
+                .join("\n")),
+            format => Err(anyhow!("Format {format} can not hold multiple structures")),
+        }
+    }
+
+    /// Read a file with a single structure, files with multiple structures like trajectories
+    /// are errors, read them by `input_frame` or `input_all` instead
     pub fn input<R: Read>(format: &str, r: R) -> Result<Self> {
         match format {
-            "xyz" => Self::input_from_xyz(r),
             "mol2" => Self::input_from_mol2(r),
+            "mol" | "mol_v3000" => Self::input_from_mol(r),
+            "xyz" | "extxyz" | "sdf" | "sdf_v3000" | "gaussian" | "orca" | "pdb" | "cif"
+            | "mmcif" => {
+                let mut frames = Self::input_all(format, r)?;
+                if frames.len() > 1 {
+                    Err(anyhow!(
+                        "Found {} structures in the {format} file, select one of them by a frame",
+                        frames.len()
+                    ))?
+                }
+                frames
+                    .pop()
+                    .with_context(|| format!("No atoms found in the {format} file"))
+            }
+            "poscar" => Self::input_from_poscar(r),
+            "cp2k" => Self::input_from_cp2k(r),
             "lme_json" => Ok(serde_json::from_reader(r)?),
             format => Err(anyhow!("Unsupported format {format}")),
         }
     }
 
-    fn input_from_xyz<R: Read>(mut r: R) -> Result<Self> {
-        let mut content = String::new();
-        r.read_to_string(&mut content)?;
-        let lines = content.lines();
-        let mut lines = lines.filter(|line| line.len() != 0);
-        let amount: usize = lines
-            .next()
-            .with_context(|| "Unable to read count line of XYZ file")?
-            .parse()
-            .with_context(|| "Count line is not a integer")?;
-        let title = lines
-            .next()
-            .with_context(|| "Unable to read title line of XYZ file")?;
-        let atoms: Vec<_> = lines
-            .chain(std::iter::empty())
-            .map(|line| {
-                let items = line
-                    .split(" ")
-                    .filter(|item| item.len() != 0)
-                    .collect::<Vec<_>>();
-                let element = items.get(0).with_context(|| {
-                    format!("Invalid atom line {line} in XYZ file, no element token found")
-                })?;
-                let element = element_symbol_to_num(element)
-                    .with_context(|| format!("Invalid element token in {line}"))?;
-                let x = items
-                    .get(1)
-                    .with_context(|| {
-                        format!("Invalid atom line {line} in XYZ file, no x token found")
-                    })?
-                    .parse()
-                    .with_context(|| format!("Unable to parse x token in line {line}"))?;
-                let y = items
-                    .get(2)
-                    .with_context(|| {
-                        format!("Invalid atom line {line} in XYZ file, no y token found")
-                    })?
-                    .parse()
-                    .with_context(|| format!("Unable to parse y token in line {line}"))?;
-                let z = items
-                    .get(3)
-                    .with_context(|| {
-                        format!("Invalid atom line {line} in XYZ file, no z token found")
-                    })?
-                    .parse()
-                    .with_context(|| format!("Unable to parse z token in line {line}"))?;
-                let position = Point3::new(x, y, z);
-                Ok(Atom3D {
-                    element,
-                    position,
-                    formal_charge: 0.,
+    /// Read the given frame of files with multiple structures, see `input_all`
+    pub fn input_frame<R: Read>(format: &str, r: R, frame: Frame) -> Result<Self> {
+        frame.select(Self::input_all(format, r)?)
+    }
+
+    /// Read all structures in the file, like frames of XYZ files, records of SDF files, models
+    /// of PDB and mmCIF files and optimization steps of Gaussian and ORCA outputs, other formats
+    /// have only one structure
+    pub fn input_all<R: Read>(format: &str, mut r: R) -> Result<Vec<Self>> {
+        match format {
+            "sdf" | "sdf_v3000" => {
+                let mut content = String::new();
+                r.read_to_string(&mut content)?;
+                content
+                    .split("$$$$")
+                    .filter(|record| !record.trim().is_empty())
+                    .enumerate()
+                    .map(|(index, record)| {
+                        Self::input_from_mol(record.trim_start_matches(['\r', '\n']).as_bytes())
+                            .with_context(|| {
+                                format!("Unable to read record {} of SDF file", index + 1)
+                            })
+                    })
+                    .collect()
+            }
+            "xyz" | "extxyz" | "gaussian" | "orca" | "pdb" | "cif" | "mmcif" => {
+                let mut content = String::new();
+                r.read_to_string(&mut content)?;
+                match format {
+                    "xyz" | "extxyz" => Self::input_from_xyz(&content),
+                    "gaussian" => Self::input_from_gaussian(&content),
+                    "orca" => Self::input_from_orca(&content),
+                    "pdb" => Self::input_from_pdb(&content),
+                    _ => Self::input_from_cif(&content),
+                }
+            }
+            format => Ok(vec![Self::input(format, r)?]),
+        }
+    }
+
+    /// Read all frames of an XYZ file, each with a count line, a comment line and the atom
+    /// lines. The energy in the comment line is kept as the `energy` property.
+    fn input_from_xyz(content: &str) -> Result<Vec<Self>> {
+        let mut frames = vec![];
+        let mut lines = content.lines();
+        while let Some(line) = lines.by_ref().find(|line| !line.trim().is_empty()) {
+            let frame = frames.len() + 1;
+            let amount: usize = line.trim().parse().with_context(|| {
+                format!("Count line {line} of frame {frame} in XYZ file is not a integer")
+            })?;
+            let title = lines.next().with_context(|| {
+                format!("Unable to read title line of frame {frame} in XYZ file")
+            })?;
+            let atoms = lines
+                .by_ref()
+                .take(amount)
+                .map(|line| {
+                    let items = line.split_whitespace().collect::<Vec<_>>();
+                    let element = items.first().with_context(|| {
+                        format!("Invalid atom line {line} in XYZ file, no element token found")
+                    })?;
+                    // Ghost atoms like `C-Bq` are read as hidden atoms
+                    let (element, hidden) = match element.strip_suffix("-Bq") {
+                        Some(element) => (element, true),
+                        None => (*element, false),
+                    };
+                    let element = element_symbol_to_num(element)
+                        .with_context(|| format!("Invalid element token in {line}"))?;
+                    let coordinate = |index: usize, axis: &str| -> Result<f64> {
+                        items
+                            .get(index)
+                            .with_context(|| {
+                                format!(
+                                    "Invalid atom line {line} in XYZ file, no {axis} token found"
+                                )
+                            })?
+                            .parse()
+                            .with_context(|| format!("Unable to parse {axis} token in line {line}"))
+                    };
+                    let (x, y, z) = (
+                        coordinate(1, "x")?,
+                        coordinate(2, "y")?,
+                        coordinate(3, "z")?,
+                    );
+                    Ok(Atom3D {
+                        element,
+                        position: Point3::new(x, y, z),
+                        hidden,
+                        ..Default::default()
+                    })
                 })
-            })
-            .collect::<Result<Vec<_>>>()?;
-        if amount != atoms.len() {
-            Err(anyhow!(
-                "Count of atom lines is not matched to count line: {} vs. {}",
-                atoms.len(),
-                amount
-            ))
+                .collect::<Result<Vec<_>>>()?;
+            if amount != atoms.len() {
+                return Err(anyhow!(
+                    "Count of atom lines is not matched to count line in frame {frame}: {} vs. {}",
+                    atoms.len(),
+                    amount
+                ));
+            }
+            let mut molecule = Self::new(title.to_string(), atoms, vec![]);
+            molecule.lattice = read_extxyz_lattice(title)?;
+            if let Some(energy) = comment_energy(title) {
+                molecule.properties.insert("energy".to_string(), energy);
+            }
+            frames.push(molecule);
+        }
+        if frames.is_empty() {
+            Err(anyhow!("Unable to read count line of XYZ file"))
         } else {
-            Ok(Self {
-                title: title.to_string(),
-                atoms,
-                bonds: vec![],
-            })
+            Ok(frames)
         }
     }
 
@@ -212,20 +453,27 @@ impl BasicIOMolecule {
             .take_while(|line| !line.starts_with("@<TRIPOS>"))
             .filter(|line| line != &"");
         let bond_block = lines
+            .clone()
             .skip_while(|line| line != &"@<TRIPOS>BOND")
             .skip(1)
             .take_while(|line| !line.starts_with("@<TRIPOS>"))
             .filter(|line| line != &"");
+        let mut atom_attr_block = lines
+            .skip_while(|line| line != &"@<TRIPOS>UNITY_ATOM_ATTR")
+            .skip(1)
+            .take_while(|line| !line.starts_with("@<TRIPOS>"))
+            .filter(|line| line != &"");
         let title = molecule_block
             .next()
             .with_context(|| format!("Unable to read title line of the mol2 file"))?;
-        let atoms = atom_block
+        let mut atoms = atom_block
             .map(|line| {
-                let mut line_items = line.split(" ").filter(|item| item != &"").skip(1);
-                // Do not read atom name from mol2, because different programs use different for this.
-                let _ = line_items.next().with_context(|| {
-                    format!("Unable to read element token of atom in line {line}")
-                })?;
+                let mut line_items = line.split_whitespace().skip(1);
+                // The atom name is kept as an attribute only, because different programs use
+                // different conventions for it, the element is read from the atom type.
+                let name = line_items
+                    .next()
+                    .with_context(|| format!("Unable to read name token of atom in line {line}"))?;
                 let x = line_items
                     .next()
                     .with_context(|| format!("Unable to read x token of atom in line {line}"))?
@@ -245,26 +493,58 @@ impl BasicIOMolecule {
                     .split(".")
                     .next()
                     .with_context(|| format!("Unable to read element token {line}"))?;
-                let element = element_symbol_to_num(element).with_context(|| {
-                    format!("Unable to convert {} to a element number", element)
-                })?;
-                let _ = line_items
-                    .next()
-                    .with_context(|| format!("Residue ID not found in line {line}"))?;
-                let _ = line_items
+                // Dummy atoms are hidden atoms, with the element from the atom name
+                let hidden = element == "Du";
+                let element = if hidden {
+                    element_from_atom_name(name)
+                } else {
+                    element_symbol_to_num(element)
+                }
+                .with_context(|| format!("Unable to convert {} to a element number", element))?;
+                let residue_id = line_items
                     .next()
-                    .with_context(|| format!("Residue Name not found in line {line}"))?;
-                let formal_charge = line_items
+                    .map(|item| item.parse())
+                    .transpose()
+                    .with_context(|| format!("Unable to parse residue ID in line {line}"))?;
+                let residue_name = line_items.next().map(|item| item.to_string());
+                let partial_charge = line_items
                     .next()
-                    .with_context(|| format!("Residue ID not found in line {line}"))?
-                    .parse()?;
+                    .map(|item| item.parse())
+                    .transpose()
+                    .with_context(|| format!("Unable to parse partial charge in line {line}"))?;
                 Ok(Atom3D {
                     element,
                     position: Point3::new(x, y, z),
-                    formal_charge,
+                    partial_charge,
+                    name: Some(name.to_string()),
+                    residue_id,
+                    residue_name,
+                    hidden,
+                    ..Default::default()
                 })
             })
             .collect::<Result<Vec<_>>>()?;
+        // Formal charges are recorded in the optional UNITY_ATOM_ATTR section
+        while let Some(line) = atom_attr_block.next() {
+            let mut line_items = line.split_whitespace();
+            let (Some(atom_id), Some(count)) = (line_items.next(), line_items.next()) else {
+                Err(anyhow!("Invalid atom attribute header {line} in mol2 file"))?
+            };
+            let atom_id: usize = atom_id.parse()?;
+            let count: usize = count.parse()?;
+            let atom = atom_id
+                .checked_sub(1)
+                .and_then(|index| atoms.get_mut(index))
+                .with_context(|| format!("Atom {atom_id} in atom attributes not found"))?;
+            for _ in 0..count {
+                let line = atom_attr_block
+                    .next()
+                    .with_context(|| format!("Missing attributes of atom {atom_id}"))?;
+                if let Some(("charge", value)) = line.trim().split_once(char::is_whitespace) {
+                    atom.formal_charge = value.trim().parse()?;
+                }
+            }
+        }
         let bonds = bond_block
             .map(|line| {
                 let mut line_items = line.split(" ").filter(|item| item != &"").skip(1);
@@ -297,22 +577,726 @@ impl BasicIOMolecule {
             title: title.to_string(),
             atoms,
             bonds,
+            lattice: None,
+            charge: None,
+            multiplicity: None,
+            ids: BTreeMap::new(),
+            groups: BTreeMap::new(),
+            properties: BTreeMap::new(),
         })
     }
 
-    fn output_to_xyz(&self) -> Result<String> {
-        let title = self.title.clone();
+    /// Read a VASP POSCAR file, element symbols must be given in the sixth line
+    fn input_from_poscar<R: Read>(mut r: R) -> Result<Self> {
+        let mut content = String::new();
+        r.read_to_string(&mut content)?;
+        let mut lines = content.lines();
+        let title = lines
+            .next()
+            .with_context(|| "Unable to read title line of POSCAR file")?;
+        let scale = lines
+            .next()
+            .with_context(|| "Unable to read scale line of POSCAR file")?
+            .split_whitespace()
+            .map(|item| item.parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| "Unable to parse scale line of POSCAR file")?;
+        let vectors = (0..3)
+            .map(|_| {
+                let line = lines
+                    .next()
+                    .with_context(|| "Unable to read lattice vectors of POSCAR file")?;
+                read_vector(line.split_whitespace())
+                    .with_context(|| format!("Invalid lattice vector line {line}"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let lattice = Lattice::new(vectors[0], vectors[1], vectors[2]);
+        // A negative scale is the volume of the cell, and three values scale each axis
+        let scale = match scale[..] {
+            [scale] if scale < 0. => Vector3::repeat((-scale / lattice.volume()).cbrt()),
+            [scale] => Vector3::repeat(scale),
+            [x, y, z] => Vector3::new(x, y, z),
+            _ => Err(anyhow!("Invalid scale line in POSCAR file"))?,
+        };
+        let lattice = Lattice(lattice.0.map(|vector| vector.component_mul(&scale)));
+        let symbols = lines
+            .next()
+            .with_context(|| "Unable to read element symbols of POSCAR file")?
+            .split_whitespace()
+            .map(|symbol| {
+                // Symbols of POTCAR files like `Fe_pv` are accepted
+                let symbol = symbol.split(['_', '/']).next().unwrap_or(symbol);
+                element_symbol_to_num(symbol)
+                    .with_context(|| format!("Invalid element symbol {symbol} in POSCAR file"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let counts = lines
+            .next()
+            .with_context(|| "Unable to read atom counts of POSCAR file")?
+            .split_whitespace()
+            .map(|count| count.parse::<usize>())
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| "Unable to parse atom counts of POSCAR file")?;
+        if symbols.len() != counts.len() {
+            Err(anyhow!(
+                "Count of element symbols is not matched to atom counts: {} vs. {}",
+                symbols.len(),
+                counts.len()
+            ))?
+        }
+        let mut mode = lines
+            .next()
+            .with_context(|| "Unable to read coordinate mode of POSCAR file")?;
+        if mode.trim_start().starts_with(['S', 's']) {
+            mode = lines
+                .next()
+                .with_context(|| "Unable to read coordinate mode of POSCAR file")?;
+        }
+        let cartesian = mode.trim_start().starts_with(['C', 'c', 'K', 'k']);
+        let atoms = symbols
+            .into_iter()
+            .zip(counts)
+            .flat_map(|(element, count)| std::iter::repeat_n(element, count))
+            .map(|element| {
+                let line = lines
+                    .next()
+                    .with_context(|| "Count of atom lines is less than atom counts")?;
+                let coordinate = read_vector(line.split_whitespace())
+                    .with_context(|| format!("Invalid atom line {line} in POSCAR file"))?;
+                let position = if cartesian {
+                    coordinate.component_mul(&scale)
+                } else {
+                    lattice.to_cartesian(coordinate)
+                };
+                Ok(Atom3D {
+                    element,
+                    position: Point3::from(position),
+                    ..Default::default()
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            title: title.to_string(),
+            atoms,
+            bonds: vec![],
+            lattice: Some(lattice),
+            charge: None,
+            multiplicity: None,
+            ids: BTreeMap::new(),
+            groups: BTreeMap::new(),
+            properties: BTreeMap::new(),
+        })
+    }
+
+    /// Read the `&CELL` and `&COORD` sections of a CP2K input file
+    fn input_from_cp2k<R: Read>(mut r: R) -> Result<Self> {
+        let mut content = String::new();
+        r.read_to_string(&mut content)?;
+        let section = |name: &'static str| {
+            content
+                .lines()
+                .map(|line| line.trim())
+                .skip_while(move |line| !line.eq_ignore_ascii_case(&format!("&{name}")))
+                .skip(1)
+                .take_while(|line| !line.to_uppercase().starts_with("&END"))
+                .filter(|line| !line.is_empty() && !line.starts_with(['#', '!']))
+        };
+        let mut vectors = BTreeMap::new();
+        let mut parameters = None;
+        let mut angles = Vector3::repeat(90.);
+        for line in section("CELL") {
+            // Unit tokens like `[angstrom]` are ignored, coordinates are always in Angstrom
+            let mut items = line
+                .split_whitespace()
+                .filter(|item| !item.starts_with('['));
+            let keyword = items.next().unwrap_or_default().to_uppercase();
+            match keyword.as_str() {
+                "A" | "B" | "C" => {
+                    let vector = read_vector(items)
+                        .with_context(|| format!("Invalid cell vector line {line}"))?;
+                    vectors.insert(keyword, vector);
+                }
+                "ABC" => {
+                    parameters = Some(
+                        read_vector(items).with_context(|| format!("Invalid cell line {line}"))?,
+                    )
+                }
+                "ALPHA_BETA_GAMMA" => {
+                    angles =
+                        read_vector(items).with_context(|| format!("Invalid cell line {line}"))?
+                }
+                _ => {}
+            }
+        }
+        let lattice = if let Some(lengths) = parameters {
+            Some(Lattice::from_parameters(
+                lengths.x, lengths.y, lengths.z, angles.x, angles.y, angles.z,
+            ))
+        } else if let (Some(a), Some(b), Some(c)) =
+            (vectors.get("A"), vectors.get("B"), vectors.get("C"))
+        {
+            Some(Lattice::new(*a, *b, *c))
+        } else {
+            None
+        };
+        let mut scaled = false;
+        let mut atoms = vec![];
+        for line in section("COORD") {
+            let items = line.split_whitespace().collect::<Vec<_>>();
+            match items[0].to_uppercase().as_str() {
+                "SCALED" => {
+                    scaled = matches!(items.get(1), None | Some(&"T" | &"TRUE" | &".TRUE."))
+                }
+                "UNIT" => {}
+                _ => {
+                    // Kinds like `O1` or `H_w` are named after the element
+                    let symbol = items[0]
+                        .split(|c: char| !c.is_ascii_alphabetic())
+                        .next()
+                        .unwrap_or_default();
+                    let element = element_symbol_to_num(symbol)
+                        .with_context(|| format!("Invalid element token in {line}"))?;
+                    let coordinate = read_vector(items[1..].iter().copied())
+                        .with_context(|| format!("Invalid atom line {line} in CP2K file"))?;
+                    let position = match (scaled, &lattice) {
+                        (true, Some(lattice)) => lattice.to_cartesian(coordinate),
+                        (true, None) => Err(anyhow!("Scaled coordinates found without cell"))?,
+                        (false, _) => coordinate,
+                    };
+                    atoms.push(Atom3D {
+                        element,
+                        position: Point3::from(position),
+                        ..Default::default()
+                    });
+                }
+            }
+        }
+        Ok(Self {
+            title: String::new(),
+            atoms,
+            bonds: vec![],
+            lattice,
+            charge: None,
+            multiplicity: None,
+            ids: BTreeMap::new(),
+            groups: BTreeMap::new(),
+            properties: BTreeMap::new(),
+        })
+    }
+
+    /// Read a MOL file or the first record of a SDF file, in both V2000 and V3000 formats.
+    /// Charges, isotopes and radicals are read from the properties block, radicals are
+    /// kept in the `radical` tag of atoms.
+    fn input_from_mol<R: Read>(mut r: R) -> Result<Self> {
+        let mut content = String::new();
+        r.read_to_string(&mut content)?;
+        let mut lines = content.lines();
+        let title = lines
+            .next()
+            .with_context(|| "Unable to read title line of MOL file")?;
+        let counts = lines
+            .nth(2)
+            .with_context(|| "Unable to read counts line of MOL file")?;
+        let (atoms, bonds) = if counts.contains("V3000") {
+            read_mol_v3000(lines)?
+        } else {
+            read_mol_v2000(counts, lines)?
+        };
+        Ok(Self {
+            title: title.trim().to_string(),
+            atoms,
+            bonds,
+            lattice: None,
+            charge: None,
+            multiplicity: None,
+            ids: BTreeMap::new(),
+            groups: BTreeMap::new(),
+            properties: read_sdf_data(&content),
+        })
+    }
+
+    /// Read geometries of a Gaussian log file from the input orientation, or the standard
+    /// orientation if `nosymm` is not set. The SCF energy is kept in each geometry, and the
+    /// thermochemistry energies and convergence are kept in the last one.
+    fn input_from_gaussian(content: &str) -> Result<Vec<Self>> {
+        let orientation = if content.contains("Input orientation:") {
+            "Input orientation:"
+        } else {
+            "Standard orientation:"
+        };
+        let mut frames: Vec<Self> = vec![];
+        let (mut charge, mut multiplicity) = (None, None);
+        let mut lines = content.lines();
+        while let Some(line) = lines.next() {
+            let line = line.trim();
+            if line == orientation {
+                // Dashes and two header lines before the atoms
+                let atoms = lines
+                    .by_ref()
+                    .skip(4)
+                    .take_while(|line| !line.trim_start().starts_with("---"))
+                    .map(|line| {
+                        let items = line.split_whitespace().collect::<Vec<_>>();
+                        let element = items
+                            .get(1)
+                            .and_then(|element| element.parse().ok())
+                            .with_context(|| format!("Invalid atom line {line} in Gaussian log"))?;
+                        let position =
+                            read_vector(items[items.len().saturating_sub(3)..].iter().copied())
+                                .with_context(|| {
+                                    format!("Invalid atom line {line} in Gaussian log")
+                                })?;
+                        Ok(Atom3D {
+                            element,
+                            position: Point3::from(position),
+                            ..Default::default()
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let mut frame = Self::new(String::new(), atoms, vec![]);
+                (frame.charge, frame.multiplicity) = (charge, multiplicity);
+                frames.push(frame);
+            } else if let Some(values) = line.strip_prefix("Charge =") {
+                let mut items = values.split_whitespace();
+                charge = items.next().and_then(|charge| charge.parse().ok());
+                multiplicity = items
+                    .nth(2)
+                    .and_then(|multiplicity| multiplicity.parse().ok());
+            } else if let Some(energy) = line.strip_prefix("SCF Done:") {
+                let energy = energy
+                    .split('=')
+                    .nth(1)
+                    .and_then(|energy| energy.split_whitespace().next()?.parse().ok())
+                    .with_context(|| format!("Invalid SCF energy line {line} in Gaussian log"))?;
+                if let Some(frame) = frames.last_mut() {
+                    frame.properties.insert("scf_energy".to_string(), energy);
+                }
+            } else if let Some((name, value)) = line.split_once('=') {
+                let name = match name.trim() {
+                    "Zero-point correction" => "zero_point_correction",
+                    "Sum of electronic and zero-point Energies" => "zero_point_energy",
+                    "Sum of electronic and thermal Energies" => "thermal_energy",
+                    "Sum of electronic and thermal Enthalpies" => "enthalpy",
+                    "Sum of electronic and thermal Free Energies" => "free_energy",
+                    _ => continue,
+                };
+                let value = value
+                    .split_whitespace()
+                    .next()
+                    .and_then(|value| value.parse().ok())
+                    .with_context(|| format!("Invalid energy line {line} in Gaussian log"))?;
+                if let Some(frame) = frames.last_mut() {
+                    frame.properties.insert(name.to_string(), value);
+                }
+            }
+        }
+        let last = frames
+            .last_mut()
+            .with_context(|| "No geometry found in Gaussian log")?;
+        // 1 for converged optimizations or normal termination, 0 otherwise
+        let status = |converged: bool| if converged { 1. } else { 0. };
+        if content.contains("Berny optimization") {
+            last.properties.insert(
+                "converged".to_string(),
+                status(content.contains("Optimization completed")),
+            );
+        }
+        last.properties.insert(
+            "normal_termination".to_string(),
+            status(
+                content
+                    .lines()
+                    .rev()
+                    .find(|line| !line.trim().is_empty())
+                    .is_some_and(|line| line.contains("Normal termination")),
+            ),
+        );
+        Ok(frames)
+    }
+
+    /// Read geometries of an ORCA output file, or the XYZ file written by ORCA with the
+    /// energy in the comment line. The final single point energy is kept in each geometry,
+    /// and the thermochemistry energies and convergence are kept in the last one.
+    fn input_from_orca(content: &str) -> Result<Vec<Self>> {
+        let is_xyz = content
+            .lines()
+            .find(|line| !line.trim().is_empty())
+            .is_some_and(|line| line.trim().parse::<usize>().is_ok());
+        if is_xyz {
+            return Self::input_from_xyz(content);
+        }
+        let mut frames: Vec<Self> = vec![];
+        let (mut charge, mut multiplicity) = (None, None);
+        let mut lines = content.lines();
+        // The last number of lines like `Total Charge  Charge  ....  0`
+        let value = |line: &str| {
+            line.split_whitespace()
+                .last()
+                .and_then(|value| value.parse().ok())
+        };
+        while let Some(line) = lines.next() {
+            let line = line.trim();
+            if line == "CARTESIAN COORDINATES (ANGSTROEM)" {
+                let atoms = lines
+                    .by_ref()
+                    .skip(1)
+                    .take_while(|line| !line.trim().is_empty())
+                    .map(|line| {
+                        let items = line.split_whitespace().collect::<Vec<_>>();
+                        // Ghost atoms are written like `H:` or `H :`
+                        let symbol = items.first().map(|symbol| symbol.trim_end_matches(':'));
+                        let element = symbol
+                            .and_then(element_symbol_to_num)
+                            .with_context(|| format!("Invalid atom line {line} in ORCA output"))?;
+                        let position =
+                            read_vector(items[items.len().saturating_sub(3)..].iter().copied())
+                                .with_context(|| {
+                                    format!("Invalid atom line {line} in ORCA output")
+                                })?;
+                        Ok(Atom3D {
+                            element,
+                            position: Point3::from(position),
+                            hidden: items.iter().any(|item| item.ends_with(':')),
+                            ..Default::default()
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let mut frame = Self::new(String::new(), atoms, vec![]);
+                (frame.charge, frame.multiplicity) = (charge, multiplicity);
+                frames.push(frame);
+            } else if line.starts_with("Total Charge") {
+                charge = value(line).map(|charge: f64| charge as i64).or(charge);
+            } else if line.starts_with("Multiplicity") {
+                multiplicity = value(line)
+                    .map(|multiplicity: f64| multiplicity as usize)
+                    .or(multiplicity);
+            } else {
+                let name = if line.starts_with("FINAL SINGLE POINT ENERGY") {
+                    "energy"
+                } else if line.starts_with("Total Enthalpy") {
+                    "enthalpy"
+                } else if line.starts_with("Final Gibbs free energy") {
+                    "free_energy"
+                } else {
+                    continue;
+                };
+                // Energies are followed by the unit like `Eh` in thermochemistry
+                let energy = line
+                    .split_whitespace()
+                    .rev()
+                    .find_map(|item| item.parse::<f64>().ok())
+                    .with_context(|| format!("Invalid energy line {line} in ORCA output"))?;
+                if let Some(frame) = frames.last_mut() {
+                    frame.properties.insert(name.to_string(), energy);
+                }
+            }
+        }
+        let last = frames
+            .last_mut()
+            .with_context(|| "No geometry found in ORCA output")?;
+        let status = |converged: bool| if converged { 1. } else { 0. };
+        if content.contains("GEOMETRY OPTIMIZATION CYCLE") {
+            last.properties.insert(
+                "converged".to_string(),
+                status(content.contains("THE OPTIMIZATION HAS CONVERGED")),
+            );
+        }
+        last.properties.insert(
+            "normal_termination".to_string(),
+            status(content.contains("ORCA TERMINATED NORMALLY")),
+        );
+        Ok(frames)
+    }
+
+    /// Read models of a PDB file from `ATOM`, `HETATM`, `CRYST1` and `CONECT` records, only
+    /// the first alternate location of atoms is kept. Bonds repeated in the `CONECT` records
+    /// of an atom are read as double or triple bonds.
+    fn input_from_pdb(content: &str) -> Result<Vec<Self>> {
+        let mut title = vec![];
+        let mut lattice = None;
+        let mut models: Vec<(Vec<Atom3D>, BTreeMap<String, usize>)> = vec![];
+        let mut new_model = true;
+        let mut conect = BTreeMap::<(String, String), usize>::new();
+        for line in content.lines() {
+            let record = column(line, 0..6);
+            match record {
+                "TITLE" => title.push(column(line, 10..80).to_string()),
+                "CRYST1" => {
+                    let parameters = [6..15, 15..24, 24..33, 33..40, 40..47, 47..54]
+                        .map(|range| column(line, range).parse::<f64>())
+                        .into_iter()
+                        .collect::<Result<Vec<_>, _>>()
+                        .with_context(|| format!("Invalid CRYST1 record {line}"))?;
+                    // Unit cells of 1 Angstrom are placeholders for non-periodic structures
+                    if parameters[..3] != [1., 1., 1.] {
+                        lattice = Some(Lattice::from_parameters(
+                            parameters[0],
+                            parameters[1],
+                            parameters[2],
+                            parameters[3],
+                            parameters[4],
+                            parameters[5],
+                        ));
+                    }
+                }
+                "MODEL" => new_model = true,
+                "ENDMDL" => new_model = true,
+                "ATOM" | "HETATM" => {
+                    if new_model {
+                        models.push(Default::default());
+                        new_model = false;
+                    }
+                    if !["", "A", "1"].contains(&column(line, 16..17)) {
+                        continue;
+                    }
+                    let position = read_vector(
+                        [30..38, 38..46, 46..54]
+                            .map(|range| column(line, range))
+                            .into_iter(),
+                    )
+                    .with_context(|| format!("Invalid atom line {line} in PDB file"))?;
+                    let name = column(line, 12..16);
+                    let element = match column(line, 76..78) {
+                        "" => element_from_atom_name(line.get(12..16).unwrap_or(name)),
+                        symbol => element_symbol_to_num(symbol),
+                    }
+                    .with_context(|| format!("Unable to read element of atom in line {line}"))?;
+                    let formal_charge = match column(line, 78..80) {
+                        "" => 0.,
+                        charge => {
+                            let value = charge.trim_matches(['+', '-']).parse::<f64>()?;
+                            if charge.contains('-') {
+                                -value
+                            } else {
+                                value
+                            }
+                        }
+                    };
+                    let residue_id = match column(line, 22..26) {
+                        "" => None,
+                        residue_id => Some(residue_id.parse().with_context(|| {
+                            format!("Unable to parse residue number in line {line}")
+                        })?),
+                    };
+                    let optional = |range| {
+                        Some(column(line, range).to_string()).filter(|item| !item.is_empty())
+                    };
+                    let mut atom = Atom3D {
+                        element,
+                        position: Point3::from(position),
+                        formal_charge,
+                        name: Some(name.to_string()),
+                        residue_id,
+                        residue_name: optional(17..21),
+                        chain: optional(21..22),
+                        ..Default::default()
+                    };
+                    if let Some(insertion_code) = optional(26..27) {
+                        atom.tags
+                            .insert("insertion_code".to_string(), insertion_code);
+                    }
+                    let (atoms, serials) = models.last_mut().expect("Model created above");
+                    serials.insert(column(line, 6..11).to_string(), atoms.len());
+                    atoms.push(atom);
+                }
+                "CONECT" => {
+                    let atom = column(line, 6..11).to_string();
+                    for range in [11..16, 16..21, 21..26, 26..31] {
+                        let bonded = column(line, range);
+                        if !bonded.is_empty() {
+                            *conect
+                                .entry((atom.clone(), bonded.to_string()))
+                                .or_default() += 1;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        let title = title.join(" ");
+        // A bond may be listed by one or both of its atoms
+        let mut bonds = BTreeMap::<(String, String), usize>::new();
+        for ((a, b), count) in conect {
+            let order = bonds
+                .entry(if a < b { (a, b) } else { (b, a) })
+                .or_default();
+            *order = count.clamp(*order, 3);
+        }
+        Ok(models
+            .into_iter()
+            .map(|(atoms, serials)| {
+                let bonds = bonds
+                    .iter()
+                    .filter_map(|((a, b), order)| {
+                        Some((*serials.get(a)?, *serials.get(b)?, *order as f64))
+                    })
+                    .collect();
+                let (ids, groups) = residue_namespace(&atoms);
+                Self {
+                    title: title.clone(),
+                    atoms,
+                    bonds,
+                    lattice,
+                    charge: None,
+                    multiplicity: None,
+                    ids,
+                    groups,
+                    properties: BTreeMap::new(),
+                }
+            })
+            .collect())
+    }
+
+    /// Read models of a mmCIF file from the `_atom_site` loop and the `_cell` items,
+    /// author defined residue numbers and chains are used if given
+    fn input_from_cif(content: &str) -> Result<Vec<Self>> {
+        let (title, items, loops) = read_cif(content)?;
+        let cell = [
+            "length_a",
+            "length_b",
+            "length_c",
+            "angle_alpha",
+            "angle_beta",
+            "angle_gamma",
+        ]
+        .map(|name| {
+            items
+                .get(&format!("_cell.{name}"))
+                .and_then(|value| value.split('(').next()?.parse::<f64>().ok())
+        });
+        let lattice = match cell {
+            [Some(a), Some(b), Some(c), alpha, beta, gamma] if [a, b, c] != [1., 1., 1.] => {
+                Some(Lattice::from_parameters(
+                    a,
+                    b,
+                    c,
+                    alpha.unwrap_or(90.),
+                    beta.unwrap_or(90.),
+                    gamma.unwrap_or(90.),
+                ))
+            }
+            _ => None,
+        };
+        let (headers, values) = loops
+            .iter()
+            .find(|(headers, _)| headers.iter().any(|header| header == "_atom_site.Cartn_x"))
+            .with_context(|| "No _atom_site loop found in mmCIF file")?;
+        let field = |names: &[&str]| {
+            names.iter().find_map(|name| {
+                headers
+                    .iter()
+                    .position(|header| header == &format!("_atom_site.{name}"))
+            })
+        };
+        // Values of `.` and `?` are missing ones
+        let present = |value: &&String| !["", ".", "?"].contains(&value.as_str());
+        let fields = [
+            field(&["Cartn_x"]),
+            field(&["Cartn_y"]),
+            field(&["Cartn_z"]),
+            field(&["type_symbol"]),
+            field(&["auth_atom_id", "label_atom_id"]),
+            field(&["auth_comp_id", "label_comp_id"]),
+            field(&["auth_seq_id", "label_seq_id"]),
+            field(&["auth_asym_id", "label_asym_id"]),
+            field(&["pdbx_formal_charge"]),
+            field(&["label_alt_id"]),
+            field(&["pdbx_PDB_ins_code"]),
+            field(&["pdbx_PDB_model_num"]),
+        ];
+        let mut models: Vec<(String, Vec<Atom3D>)> = vec![];
+        for row in values.chunks(headers.len()) {
+            let values = fields.map(|index| index.and_then(|index| row.get(index)).filter(present));
+            let [x, y, z, symbol, name, residue_name, residue_id, chain, charge, alt, insertion_code, model] =
+                values;
+            if alt.is_some_and(|alt| alt != "A" && alt != "1") {
+                continue;
+            }
+            let position = read_vector(
+                [x, y, z]
+                    .map(|value| value.map(|value| value.as_str()).unwrap_or_default())
+                    .into_iter(),
+            )
+            .with_context(|| format!("Invalid coordinate of atom {}", row.join(" ")))?;
+            let element = match (symbol, name) {
+                (Some(symbol), _) => element_symbol_to_num(symbol),
+                (None, Some(name)) => element_from_atom_name(name),
+                _ => None,
+            }
+            .with_context(|| format!("Unable to read element of atom {}", row.join(" ")))?;
+            let mut atom = Atom3D {
+                element,
+                position: Point3::from(position),
+                formal_charge: charge
+                    .map(|charge| charge.parse())
+                    .transpose()?
+                    .unwrap_or_default(),
+                name: name.cloned(),
+                residue_id: residue_id.and_then(|residue_id| residue_id.parse().ok()),
+                residue_name: residue_name.cloned(),
+                chain: chain.cloned(),
+                ..Default::default()
+            };
+            if let Some(insertion_code) = insertion_code {
+                atom.tags
+                    .insert("insertion_code".to_string(), insertion_code.clone());
+            }
+            let model = model.cloned().unwrap_or_default();
+            match models.last_mut() {
+                Some((current, atoms)) if current == &model => atoms.push(atom),
+                _ => models.push((model, vec![atom])),
+            }
+        }
+        Ok(models
+            .into_iter()
+            .map(|(_, atoms)| {
+                let (ids, groups) = residue_namespace(&atoms);
+                Self {
+                    title: title.clone(),
+                    atoms,
+                    bonds: vec![],
+                    lattice,
+                    charge: None,
+                    multiplicity: None,
+                    ids,
+                    groups,
+                    properties: BTreeMap::new(),
+                }
+            })
+            .collect())
+    }
+
+    /// Hidden atoms are written as ghost atoms like `C-Bq`, and the lattice is written
+    /// to the comment line in extended XYZ format
+    fn output_to_xyz(&self, extended: bool) -> Result<String> {
+        let title = match (extended, &self.lattice) {
+            (true, Some(lattice)) => format!(
+                "Lattice=\"{}\" Properties=species:S:1:pos:R:3 pbc=\"T T T\" {}",
+                lattice
+                    .0
+                    .iter()
+                    .flat_map(|vector| vector.iter())
+                    .map(|value| value.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                self.title
+            ),
+            _ => self.title.clone(),
+        };
         let count = self.atoms.len().to_string();
         let xyz = self
             .atoms
             .iter()
             .map(|atom| {
+                let element_symbol = element_num_to_symbol(atom.element)
+                    .with_context(|| format!("Invalid element number found {}", atom.element))?;
                 Ok(format!(
                     "{} {} {} {}",
-                    element_num_to_symbol(&atom.element).with_context(|| format!(
-                        "Invalid element number found {}",
-                        atom.element
-                    ))?,
+                    if atom.hidden {
+                        format!("{element_symbol}-Bq")
+                    } else {
+                        element_symbol.to_string()
+                    },
                     atom.position.x,
                     atom.position.y,
                     atom.position.z
@@ -322,6 +1306,642 @@ impl BasicIOMolecule {
         Ok([vec![count, title], xyz].concat().join("\n"))
     }
 
+    /// Atoms are written in cartesian coordinates, consecutive atoms of the same element
+    /// make up a species so the order of atoms is kept
+    fn output_to_poscar(&self) -> Result<String> {
+        let lattice = self
+            .lattice
+            .with_context(|| "Lattice is required to write POSCAR file")?;
+        let mut species: Vec<(usize, usize)> = vec![];
+        for atom in &self.atoms {
+            match species.last_mut() {
+                Some((element, count)) if element == &atom.element => *count += 1,
+                _ => species.push((atom.element, 1)),
+            }
+        }
+        let symbols = species
+            .iter()
+            .map(|(element, _)| {
+                element_num_to_symbol(element)
+                    .with_context(|| format!("Invalid element number found {}", element))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let counts = species
+            .iter()
+            .map(|(_, count)| count.to_string())
+            .collect::<Vec<_>>();
+        let vectors = lattice
+            .0
+            .iter()
+            .map(|vector| format!("{} {} {}", vector.x, vector.y, vector.z))
+            .collect::<Vec<_>>();
+        let atoms = self
+            .atoms
+            .iter()
+            .map(|atom| {
+                format!(
+                    "{} {} {}",
+                    atom.position.x, atom.position.y, atom.position.z
+                )
+            })
+            .collect::<Vec<_>>();
+        Ok([
+            vec![self.title.replace('\n', " "), "1.0".to_string()],
+            vectors,
+            vec![symbols.join(" "), counts.join(" "), "Cartesian".to_string()],
+            atoms,
+        ]
+        .concat()
+        .join("\n"))
+    }
+
+    fn output_to_cp2k(&self) -> Result<String> {
+        let cell = self
+            .lattice
+            .map(|lattice| {
+                let vectors = ["A", "B", "C"]
+                    .into_iter()
+                    .zip(lattice.0)
+                    .map(|(name, vector)| {
+                        format!("  {} {} {} {}", name, vector.x, vector.y, vector.z)
+                    })
+                    .collect::<Vec<_>>();
+                [
+                    vec!["&CELL".to_string()],
+                    vectors,
+                    vec!["&END CELL".to_string()],
+                ]
+                .concat()
+            })
+            .unwrap_or_default();
+        let atoms = self
+            .atoms
+            .iter()
+            .map(|atom| {
+                let element_symbol = element_num_to_symbol(atom.element)
+                    .with_context(|| format!("Invalid element number found {}", atom.element))?;
+                Ok(format!(
+                    "  {} {} {} {}",
+                    element_symbol, atom.position.x, atom.position.y, atom.position.z
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok([
+            cell,
+            vec!["&COORD".to_string()],
+            atoms,
+            vec!["&END COORD".to_string()],
+        ]
+        .concat()
+        .join("\n"))
+    }
+
+    /// Total charge and spin multiplicity carried from the structure, or computed as
+    /// `SparseMolecule` does from the visible atoms if they are not set
+    pub fn charge_and_multiplicity(&self) -> (i64, usize) {
+        let molecule = SparseMolecule {
+            atoms: SparseAtomList::from(self.atoms.clone()),
+            charge: self.charge,
+            multiplicity: self.multiplicity,
+            ..Default::default()
+        };
+        (molecule.total_charge(), molecule.spin_multiplicity())
+    }
+
+    /// Index of the exported atom by its id, or the index itself
+    fn atom_index(&self, atom: &str) -> Result<usize> {
+        let index = self
+            .ids
+            .get(atom)
+            .copied()
+            .or_else(|| atom.parse().ok())
+            .with_context(|| format!("Atom {atom} not found in exported atoms"))?;
+        if index < self.atoms.len() {
+            Ok(index)
+        } else {
+            Err(anyhow!("Atom {atom} not found in exported atoms"))
+        }
+    }
+
+    /// Hidden atoms are written as ghost atoms like `C-Bq`, and isotopes like `C(Iso=13)`.
+    /// Constraints are written in the ModRedundant section, which requires `modredundant`
+    /// in the route section.
+    pub fn output_to_gaussian(&self, options: &GaussianOptions) -> Result<String> {
+        let route = match options.route.trim() {
+            "" => "#p sp",
+            route => route,
+        };
+        if !options.constraints.is_empty() && !route.to_lowercase().contains("modredundant") {
+            Err(anyhow!(
+                "Constraints require modredundant in the route section {route}"
+            ))?
+        }
+        let title = match self.title.trim() {
+            "" => "lmers",
+            title => title,
+        };
+        let (charge, multiplicity) = self.charge_and_multiplicity();
+        let mut link0 = options.link0.clone();
+        if !options.link1.is_empty()
+            && !link0
+                .iter()
+                .any(|command| command.to_lowercase().starts_with("%chk"))
+        {
+            link0.insert(0, "%chk=lmers.chk".to_string());
+        }
+        let atoms = self
+            .atoms
+            .iter()
+            .map(|atom| {
+                let symbol = element_num_to_symbol(atom.element)
+                    .with_context(|| format!("Invalid element number found {}", atom.element))?;
+                let symbol = match (atom.hidden, atom.isotope) {
+                    (true, _) => format!("{symbol}-Bq"),
+                    (false, Some(isotope)) => format!("{symbol}(Iso={isotope})"),
+                    (false, None) => symbol.to_string(),
+                };
+                Ok(format!(
+                    "{:<10} {:>14.8} {:>14.8} {:>14.8}",
+                    symbol, atom.position.x, atom.position.y, atom.position.z
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let constraints = options
+            .constraints
+            .iter()
+            .map(|constraint| {
+                let kind = match constraint.len() {
+                    1 => "X",
+                    2 => "B",
+                    3 => "A",
+                    4 => "D",
+                    count => Err(anyhow!(
+                        "Constraints should have 1 to 4 atoms, found {count}"
+                    ))?,
+                };
+                let atoms = constraint
+                    .iter()
+                    .map(|atom| Ok((self.atom_index(atom)? + 1).to_string()))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(format!("{} {} F", kind, atoms.join(" ")))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let charge_line = format!("{charge} {multiplicity}");
+        let mut lines = [
+            link0.clone(),
+            vec![
+                route.to_string(),
+                String::new(),
+                title.to_string(),
+                String::new(),
+            ],
+            vec![charge_line.clone()],
+            atoms,
+            vec![String::new()],
+        ]
+        .concat();
+        if !constraints.is_empty() {
+            lines.extend(constraints);
+            lines.push(String::new());
+        }
+        for route in &options.link1 {
+            lines.push("--Link1--".to_string());
+            lines.extend(link0.clone());
+            lines.extend([
+                route.to_string(),
+                String::new(),
+                title.to_string(),
+                String::new(),
+                charge_line.clone(),
+                String::new(),
+            ]);
+        }
+        lines.push(String::new());
+        Ok(lines.join("\n"))
+    }
+
+    /// Hidden atoms are written as ghost atoms like `H:`, and isotopes with their mass numbers
+    /// as masses. Constraints are written in the `%geom` block with 0-based indexes.
+    pub fn output_to_orca(&self, options: &OrcaOptions) -> Result<String> {
+        let keywords = match options.keywords.trim() {
+            "" => "! SP",
+            keywords => keywords,
+        };
+        let (charge, multiplicity) = self.charge_and_multiplicity();
+        let constraints = options
+            .constraints
+            .iter()
+            .map(|constraint| {
+                let kind = match constraint.len() {
+                    1 => "C",
+                    2 => "B",
+                    3 => "A",
+                    4 => "D",
+                    count => Err(anyhow!(
+                        "Constraints should have 1 to 4 atoms, found {count}"
+                    ))?,
+                };
+                let atoms = constraint
+                    .iter()
+                    .map(|atom| Ok(self.atom_index(atom)?.to_string()))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(format!("    {{ {} {} C }}", kind, atoms.join(" ")))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let geom = if constraints.is_empty() {
+            vec![]
+        } else {
+            [
+                vec!["%geom".to_string(), "  Constraints".to_string()],
+                constraints,
+                vec!["  end".to_string(), "end".to_string()],
+            ]
+            .concat()
+        };
+        let atoms = self
+            .atoms
+            .iter()
+            .map(|atom| {
+                let symbol = element_num_to_symbol(atom.element)
+                    .with_context(|| format!("Invalid element number found {}", atom.element))?;
+                let mut line = format!(
+                    "{:<3} {:>14.8} {:>14.8} {:>14.8}",
+                    if atom.hidden {
+                        format!("{symbol}:")
+                    } else {
+                        symbol.to_string()
+                    },
+                    atom.position.x,
+                    atom.position.y,
+                    atom.position.z
+                );
+                if let Some(isotope) = atom.isotope {
+                    line += &format!(" M = {isotope}");
+                }
+                Ok(line)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok([
+            vec![keywords.to_string()],
+            options.blocks.clone(),
+            geom,
+            vec![format!("* xyz {charge} {multiplicity}")],
+            atoms,
+            vec!["*".to_string(), String::new()],
+        ]
+        .concat()
+        .join("\n"))
+    }
+
+    /// Atoms of standard residues are written as `ATOM` records and others as `HETATM`
+    /// records, bonds are written as `CONECT` records, repeated for double and triple bonds.
+    /// Residue names are cut to 3 characters like `UNL` for `UNL1` of mol2 files, and other
+    /// fields too long for their columns are errors.
+    fn output_to_pdb(&self) -> Result<String> {
+        if self.atoms.len() > 99999 {
+            Err(anyhow!(
+                "Unable to write {} atoms to PDB file, at most 99999",
+                self.atoms.len()
+            ))?
+        }
+        let title = match self.title.trim() {
+            "" => vec![],
+            title => vec![format!("TITLE     {}", title.replace('\n', " "))],
+        };
+        let cryst1 = self
+            .lattice
+            .map(|lattice| {
+                let (a, b, c, alpha, beta, gamma) = lattice.parameters();
+                format!(
+                    "CRYST1{:>9.3}{:>9.3}{:>9.3}{:>7.2}{:>7.2}{:>7.2} P 1           1",
+                    a, b, c, alpha, beta, gamma
+                )
+            })
+            .into_iter()
+            .collect();
+        let atoms = self
+            .atoms
+            .iter()
+            .enumerate()
+            .map(|(index, atom)| {
+                let symbol = element_num_to_symbol(atom.element)
+                    .with_context(|| format!("Invalid element number found {}", atom.element))?;
+                let name = atom.name.as_deref().unwrap_or(symbol);
+                let chain = atom.chain.as_deref().unwrap_or(" ");
+                let residue_id = atom.residue_id.unwrap_or(1);
+                let insertion_code = atom
+                    .tags
+                    .get("insertion_code")
+                    .map(|code| code.as_str())
+                    .unwrap_or(" ");
+                if name.len() > 4
+                    || chain.len() > 1
+                    || !(-999..=9999).contains(&residue_id)
+                    || insertion_code.len() > 1
+                {
+                    Err(anyhow!(
+                        "Atom {} named {name} of residue {residue_id}{insertion_code} in chain {chain} does not fit the columns of PDB file",
+                        index + 1
+                    ))?
+                }
+                // Names of one-letter elements start from the 14th column
+                let name = if name.len() < 4 && symbol.len() == 1 {
+                    format!(" {name}")
+                } else {
+                    name.to_string()
+                };
+                let residue_name = atom.residue_name.as_deref().unwrap_or("UNL");
+                let residue_name = residue_name.get(..3).unwrap_or(residue_name);
+                let record = if STANDARD_RESIDUES.contains(&residue_name) {
+                    "ATOM"
+                } else {
+                    "HETATM"
+                };
+                let charge = match atom.formal_charge.round() as i64 {
+                    0 => String::new(),
+                    charge if charge > 0 => format!("{charge}+"),
+                    charge => format!("{}-", -charge),
+                };
+                Ok(format!(
+                    "{:<6}{:>5} {:<4} {:>3} {:1}{:>4}{:1}   {:>8.3}{:>8.3}{:>8.3}{:>6.2}{:>6.2}          {:>2}{:<2}",
+                    record,
+                    index + 1,
+                    name,
+                    residue_name,
+                    chain,
+                    residue_id,
+                    insertion_code,
+                    atom.position.x,
+                    atom.position.y,
+                    atom.position.z,
+                    1.,
+                    0.,
+                    symbol.to_uppercase(),
+                    charge
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut bonded = BTreeMap::<usize, Vec<usize>>::new();
+        for (a, b, order) in &self.bonds {
+            // Aromatic bonds are written once like single bonds
+            for _ in 0..(order.floor() as usize).clamp(1, 3) {
+                bonded.entry(*a).or_default().push(*b);
+                bonded.entry(*b).or_default().push(*a);
+            }
+        }
+        let conect = bonded
+            .into_iter()
+            .flat_map(|(atom, bonded)| {
+                bonded
+                    .chunks(4)
+                    .map(|chunk| {
+                        let bonded = chunk
+                            .iter()
+                            .map(|index| format!("{:>5}", index + 1))
+                            .collect::<String>();
+                        format!("CONECT{:>5}{}", atom + 1, bonded)
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        Ok([title, cryst1, atoms, conect, vec!["END".to_string()]]
+            .concat()
+            .join("\n"))
+    }
+
+    fn output_to_cif(&self) -> Result<String> {
+        let name = self.title.split_whitespace().collect::<Vec<_>>().join("_");
+        let mut lines = vec![format!(
+            "data_{}",
+            if name.is_empty() { "lmers" } else { &name }
+        )];
+        if let Some(lattice) = self.lattice {
+            let (a, b, c, alpha, beta, gamma) = lattice.parameters();
+            lines.extend(
+                [
+                    ("length_a", a),
+                    ("length_b", b),
+                    ("length_c", c),
+                    ("angle_alpha", alpha),
+                    ("angle_beta", beta),
+                    ("angle_gamma", gamma),
+                ]
+                .map(|(name, value)| format!("_cell.{name} {value:.4}")),
+            );
+        }
+        lines.push("loop_".to_string());
+        lines.extend(
+            [
+                "group_PDB",
+                "id",
+                "type_symbol",
+                "label_atom_id",
+                "label_comp_id",
+                "label_asym_id",
+                "label_seq_id",
+                "pdbx_PDB_ins_code",
+                "Cartn_x",
+                "Cartn_y",
+                "Cartn_z",
+                "pdbx_formal_charge",
+                "auth_seq_id",
+                "auth_asym_id",
+                "pdbx_PDB_model_num",
+            ]
+            .map(|field| format!("_atom_site.{field}")),
+        );
+        for (index, atom) in self.atoms.iter().enumerate() {
+            let symbol = element_num_to_symbol(atom.element)
+                .with_context(|| format!("Invalid element number found {}", atom.element))?;
+            let residue_name = atom.residue_name.as_deref().unwrap_or("UNL");
+            let residue_id = atom.residue_id.unwrap_or(1).to_string();
+            let chain = atom.chain.as_deref().unwrap_or(".");
+            let values = [
+                if STANDARD_RESIDUES.contains(&residue_name) {
+                    "ATOM"
+                } else {
+                    "HETATM"
+                }
+                .to_string(),
+                (index + 1).to_string(),
+                symbol.to_string(),
+                cif_value(atom.name.as_deref().unwrap_or(symbol)),
+                cif_value(residue_name),
+                cif_value(chain),
+                residue_id.clone(),
+                cif_value(
+                    atom.tags
+                        .get("insertion_code")
+                        .map(|code| code.as_str())
+                        .unwrap_or("?"),
+                ),
+                format!("{:.4}", atom.position.x),
+                format!("{:.4}", atom.position.y),
+                format!("{:.4}", atom.position.z),
+                (atom.formal_charge.round() as i64).to_string(),
+                residue_id,
+                cif_value(chain),
+                "1".to_string(),
+            ];
+            lines.push(values.join(" "));
+        }
+        lines.push("#".to_string());
+        Ok(lines.join("\n"))
+    }
+
+    /// Data items of SDF records from the properties, each followed by a blank line
+    fn sdf_data(&self) -> String {
+        self.properties
+            .iter()
+            .map(|(name, value)| format!("> <{name}>\n{value}\n\n"))
+            .collect()
+    }
+
+    /// V3000 format is used if `v3000` is set or there are more than 999 atoms or bonds
+    fn output_to_mol(&self, v3000: bool) -> Result<String> {
+        let v3000 = v3000 || self.atoms.len() > 999 || self.bonds.len() > 999;
+        let symbols = self
+            .atoms
+            .iter()
+            .map(|atom| {
+                element_num_to_symbol(atom.element)
+                    .with_context(|| format!("Invalid element number found {}", atom.element))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let charges = self
+            .atoms
+            .iter()
+            .map(|atom| atom.formal_charge.round() as i64)
+            .collect::<Vec<_>>();
+        let radicals = self
+            .atoms
+            .iter()
+            .map(|atom| {
+                atom.tags
+                    .get("radical")
+                    .and_then(|radical| radical.parse::<usize>().ok())
+                    .unwrap_or_default()
+            })
+            .collect::<Vec<_>>();
+        let bond_type = |bond: f64| {
+            if bond == 1.5 {
+                4
+            } else {
+                bond.round() as usize
+            }
+        };
+        let title = self.title.replace('\n', " ");
+        let content = if v3000 {
+            let atoms = self.atoms.iter().enumerate().map(|(index, atom)| {
+                let mut line = format!(
+                    "M  V30 {} {} {:.4} {:.4} {:.4} 0",
+                    index + 1,
+                    symbols[index],
+                    atom.position.x,
+                    atom.position.y,
+                    atom.position.z
+                );
+                if charges[index] != 0 {
+                    line += &format!(" CHG={}", charges[index]);
+                }
+                if let Some(isotope) = atom.isotope {
+                    line += &format!(" MASS={isotope}");
+                }
+                if radicals[index] != 0 {
+                    line += &format!(" RAD={}", radicals[index]);
+                }
+                line
+            });
+            let bonds = self.bonds.iter().enumerate().map(|(index, (a, b, bond))| {
+                format!(
+                    "M  V30 {} {} {} {}",
+                    index + 1,
+                    bond_type(*bond),
+                    a + 1,
+                    b + 1
+                )
+            });
+            [
+                vec![
+                    title,
+                    "  lmers".to_string(),
+                    "".to_string(),
+                    "  0  0  0     0  0            999 V3000".to_string(),
+                    "M  V30 BEGIN CTAB".to_string(),
+                    format!(
+                        "M  V30 COUNTS {} {} 0 0 0",
+                        self.atoms.len(),
+                        self.bonds.len()
+                    ),
+                    "M  V30 BEGIN ATOM".to_string(),
+                ],
+                atoms.collect(),
+                vec![
+                    "M  V30 END ATOM".to_string(),
+                    "M  V30 BEGIN BOND".to_string(),
+                ],
+                bonds.collect(),
+                vec![
+                    "M  V30 END BOND".to_string(),
+                    "M  V30 END CTAB".to_string(),
+                    "M  END".to_string(),
+                ],
+            ]
+            .concat()
+        } else {
+            let atoms = self.atoms.iter().enumerate().map(|(index, atom)| {
+                // Charges from +3 to -3 are also written as codes in the atom block
+                let charge = match charges[index] {
+                    charge @ -3..=-1 => 4 - charge,
+                    charge @ 1..=3 => 4 - charge,
+                    _ => 0,
+                };
+                format!(
+                    "{:>10.4}{:>10.4}{:>10.4} {:<3} 0{:>3}  0  0  0  0  0  0  0  0  0  0",
+                    atom.position.x, atom.position.y, atom.position.z, symbols[index], charge
+                )
+            });
+            let bonds = self.bonds.iter().map(|(a, b, bond)| {
+                format!(
+                    "{:>3}{:>3}{:>3}  0  0  0  0",
+                    a + 1,
+                    b + 1,
+                    bond_type(*bond)
+                )
+            });
+            let isotopes = self
+                .atoms
+                .iter()
+                .map(|atom| atom.isotope.unwrap_or_default() as i64)
+                .collect::<Vec<_>>();
+            let radicals = radicals
+                .iter()
+                .map(|radical| *radical as i64)
+                .collect::<Vec<_>>();
+            let properties = [("CHG", &charges), ("ISO", &isotopes), ("RAD", &radicals)]
+                .into_iter()
+                .flat_map(|(name, values)| write_mol_property(name, values));
+            [
+                vec![
+                    title,
+                    "  lmers".to_string(),
+                    "".to_string(),
+                    format!(
+                        "{:>3}{:>3}  0  0  0  0  0  0  0  0999 V2000",
+                        self.atoms.len(),
+                        self.bonds.len()
+                    ),
+                ],
+                atoms.collect(),
+                bonds.collect(),
+                properties.collect(),
+                vec!["M  END".to_string()],
+            ]
+            .concat()
+        };
+        Ok(content.join("\n"))
+    }
+
     fn output_to_mol2(&self) -> Result<String> {
         let title = self.title.clone();
         let atom_count = self.atoms.len().to_string();
@@ -335,18 +1955,36 @@ impl BasicIOMolecule {
                     .with_context(|| format!("Invalid element number found {}", atom.element))?;
                 Ok(format!(
                     "{} {} {} {} {} {} {} {} {}",
-                    index,
-                    element_symbol,
+                    index + 1,
+                    atom.name.as_deref().unwrap_or(element_symbol),
                     atom.position.x,
                     atom.position.y,
                     atom.position.z,
-                    element_symbol,
-                    "1",
-                    "UNL1",
-                    atom.formal_charge
+                    // Hidden atoms are written as dummy atoms
+                    if atom.hidden { "Du" } else { element_symbol },
+                    atom.residue_id.unwrap_or(1),
+                    atom.residue_name.as_deref().unwrap_or("UNL1"),
+                    atom.partial_charge.unwrap_or_default()
                 ))
             })
             .collect::<Result<Vec<_>, Error>>()?;
+        let charge_type = if self.atoms.iter().any(|atom| atom.partial_charge.is_some()) {
+            "USER_CHARGES"
+        } else {
+            "NO_CHARGES"
+        };
+        let atom_attrs = self
+            .atoms
+            .iter()
+            .enumerate()
+            .filter(|(_, atom)| atom.formal_charge != 0.)
+            .map(|(index, atom)| format!("{} 1\ncharge {}", index + 1, atom.formal_charge))
+            .collect::<Vec<_>>();
+        let atom_attrs = if atom_attrs.is_empty() {
+            atom_attrs
+        } else {
+            [vec!["@<TRIPOS>UNITY_ATOM_ATTR".to_string()], atom_attrs].concat()
+        };
         let bonds = self
             .bonds
             .par_iter()
@@ -366,13 +2004,14 @@ impl BasicIOMolecule {
                 title,
                 format!("{} {} 0 0 0", atom_count, bond_count),
                 "SMALL".to_string(),
-                "GASTEIGER".to_string(),
+                charge_type.to_string(),
                 "".to_string(),
                 "@<TRIPOS>ATOM".to_string(),
             ],
             atoms,
             vec!["@<TRIPOS>BOND".to_string()],
             bonds,
+            atom_attrs,
         ]
         .concat()
         .into_iter()
@@ -381,3 +2020,894 @@ impl BasicIOMolecule {
         Ok(content)
     }
 }
+
+fn read_vector<'a>(mut items: impl Iterator<Item = &'a str>) -> Result<Vector3<f64>> {
+    let mut next = || -> Result<f64> {
+        Ok(items
+            .next()
+            .with_context(|| "Missing component of vector")?
+            .parse()?)
+    };
+    Ok(Vector3::new(next()?, next()?, next()?))
+}
+
+/// Energy in the comment line of XYZ files, like `energy: -5.07` of xtb, `E -76.4` of ORCA,
+/// `energy=-76.4` of extended XYZ files or a single number of CREST ensembles
+fn comment_energy(comment: &str) -> Option<f64> {
+    let items = comment.split_whitespace().collect::<Vec<_>>();
+    if let [value] = items[..] {
+        return value.parse().ok();
+    }
+    items.iter().enumerate().find_map(|(index, item)| {
+        let item = item.to_lowercase();
+        if let Some(value) = item
+            .strip_prefix("energy=")
+            .or_else(|| item.strip_prefix("e="))
+        {
+            return value.trim_matches('"').parse().ok();
+        }
+        if !["energy", "energy:", "e", "e:"].contains(&item.as_str()) {
+            return None;
+        }
+        match items.get(index + 1)? {
+            &"=" | &":" => items.get(index + 2)?.parse().ok(),
+            value => value.parse().ok(),
+        }
+    })
+}
+
+/// Read the `Lattice="..."` property from the comment line of an extended XYZ file
+fn read_extxyz_lattice(comment: &str) -> Result<Option<Lattice>> {
+    let Some((_, value)) = comment.split_once("Lattice=\"") else {
+        return Ok(None);
+    };
+    let value = value
+        .split('"')
+        .next()
+        .with_context(|| "Unterminated lattice property in XYZ file")?;
+    let values = value
+        .split_whitespace()
+        .map(|item| item.parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Unable to parse lattice property {value}"))?;
+    if values.len() != 9 {
+        Err(anyhow!("Lattice property should have 9 values: {value}"))?
+    }
+    Ok(Some(Lattice::new(
+        Vector3::new(values[0], values[1], values[2]),
+        Vector3::new(values[3], values[4], values[5]),
+        Vector3::new(values[6], values[7], values[8]),
+    )))
+}
+
+/// Residues written as `ATOM` records in PDB files, others are written as `HETATM` records
+const STANDARD_RESIDUES: [&str; 29] = [
+    "ALA", "ARG", "ASN", "ASP", "CYS", "GLN", "GLU", "GLY", "HIS", "ILE", "LEU", "LYS", "MET",
+    "PHE", "PRO", "SER", "THR", "TRP", "TYR", "VAL", "A", "C", "G", "U", "I", "DA", "DC", "DG",
+    "DT",
+];
+
+/// Element from the atom name in PDB files, names of one-letter elements start with a space or
+/// a digit, like ` CA ` for alpha carbon and `CA  ` for calcium
+fn element_from_atom_name(name: &str) -> Option<usize> {
+    let letters = name
+        .trim_start_matches(|c: char| c == ' ' || c.is_ascii_digit())
+        .chars()
+        .take_while(|c| c.is_ascii_alphabetic())
+        .collect::<String>();
+    if name.starts_with([' ', '0', '1', '2', '3', '4', '5', '6', '7', '8', '9'])
+        || letters.len() < 2
+    {
+        element_symbol_to_num(letters.get(..1)?)
+    } else {
+        element_symbol_to_num(&letters[..2]).or_else(|| element_symbol_to_num(&letters[..1]))
+    }
+}
+
+/// Ids and groups from residues of atoms, each residue is a group like `A:HIS57` and atoms in
+/// it have ids like `A:HIS57:CA`. The chain is omitted if not given, and the first atom of
+/// duplicated names is kept.
+fn residue_namespace(
+    atoms: &[Atom3D],
+) -> (BTreeMap<String, usize>, BTreeMap<String, BTreeSet<usize>>) {
+    let mut ids = BTreeMap::new();
+    let mut groups = BTreeMap::<String, BTreeSet<usize>>::new();
+    for (index, atom) in atoms.iter().enumerate() {
+        let (Some(residue_name), Some(residue_id)) = (&atom.residue_name, atom.residue_id) else {
+            continue;
+        };
+        let residue = format!(
+            "{}{}{}{}",
+            atom.chain
+                .as_ref()
+                .map(|chain| format!("{chain}:"))
+                .unwrap_or_default(),
+            residue_name,
+            residue_id,
+            atom.tags
+                .get("insertion_code")
+                .map(|code| code.as_str())
+                .unwrap_or_default()
+        );
+        if let Some(name) = &atom.name {
+            ids.entry(format!("{residue}:{name}")).or_insert(index);
+        }
+        groups.entry(residue).or_default().insert(index);
+    }
+    (ids, groups)
+}
+
+/// Data block name, items and loops of the first data block of a CIF file, loops are kept as
+/// the headers and the values of all rows.
+type CifData = (
+    String,
+    BTreeMap<String, String>,
+    Vec<(Vec<String>, Vec<String>)>,
+);
+
+fn read_cif(content: &str) -> Result<CifData> {
+    // Tokens and whether they are quoted, which are never keywords
+    let mut tokens: Vec<(String, bool)> = vec![];
+    let mut lines = content.lines();
+    while let Some(line) = lines.next() {
+        if let Some(text) = line.strip_prefix(';') {
+            let mut text = vec![text.to_string()];
+            for line in lines.by_ref() {
+                if line.starts_with(';') {
+                    break;
+                }
+                text.push(line.to_string());
+            }
+            tokens.push((text.join("\n").trim().to_string(), true));
+            continue;
+        }
+        let chars = line.chars().collect::<Vec<_>>();
+        let mut index = 0;
+        while index < chars.len() {
+            let c = chars[index];
+            if c.is_whitespace() {
+                index += 1;
+            } else if c == '#' {
+                break;
+            } else if c == '\'' || c == '"' {
+                // Quotes end before whitespace or the end of the line
+                let start = index + 1;
+                let mut end = start;
+                while end < chars.len()
+                    && !(chars[end] == c && chars.get(end + 1).is_none_or(|c| c.is_whitespace()))
+                {
+                    end += 1;
+                }
+                tokens.push((chars[start..end.min(chars.len())].iter().collect(), true));
+                index = end + 1;
+            } else {
+                let start = index;
+                while index < chars.len() && !chars[index].is_whitespace() {
+                    index += 1;
+                }
+                tokens.push((chars[start..index].iter().collect(), false));
+            }
+        }
+    }
+    let mut tokens = tokens.into_iter().peekable();
+    let mut name = String::new();
+    let mut items = BTreeMap::new();
+    let mut loops = vec![];
+    while let Some((token, _)) = tokens.next() {
+        if let Some(block) = token.strip_prefix("data_") {
+            if !name.is_empty() {
+                break;
+            }
+            name = block.to_string();
+        } else if token == "loop_" {
+            let mut headers = vec![];
+            while let Some((header, false)) = tokens.peek() {
+                if !header.starts_with('_') {
+                    break;
+                }
+                headers.push(header.clone());
+                tokens.next();
+            }
+            let mut values = vec![];
+            while let Some((value, quoted)) = tokens.peek() {
+                if !quoted
+                    && (value.starts_with('_') || value == "loop_" || value.starts_with("data_"))
+                {
+                    break;
+                }
+                values.push(value.clone());
+                tokens.next();
+            }
+            loops.push((headers, values));
+        } else if token.starts_with('_') {
+            let (value, _) = tokens
+                .next()
+                .with_context(|| format!("Missing value of {token} in CIF file"))?;
+            items.insert(token, value);
+        }
+    }
+    Ok((name, items, loops))
+}
+
+/// Values with whitespace or quotes are quoted, empty values are written as `.`
+fn cif_value(value: &str) -> String {
+    if value.is_empty() {
+        ".".to_string()
+    } else if value.contains(char::is_whitespace)
+        || value.contains(['\'', '"'])
+        || value.starts_with(['_', '#', '$', ';', '[', ']'])
+    {
+        if value.contains('"') {
+            format!("'{value}'")
+        } else {
+            format!("\"{value}\"")
+        }
+    } else {
+        value.to_string()
+    }
+}
+
+/// Atoms and bonds read from the connection table of MOL files
+type MolTable = (Vec<Atom3D>, Vec<(usize, usize, f64)>);
+
+/// Trimmed text in the columns of a fixed-width line, empty if the line is too short
+fn column(line: &str, range: std::ops::Range<usize>) -> &str {
+    let end = range.end.min(line.len());
+    line.get(range.start.min(end)..end)
+        .unwrap_or_default()
+        .trim()
+}
+
+/// Numeric data items after `M  END` of SDF records, like `> <energy>` followed by the value
+/// and a blank line. Text items are skipped since properties are numbers.
+fn read_sdf_data(record: &str) -> BTreeMap<String, f64> {
+    let mut properties = BTreeMap::new();
+    let mut lines = record
+        .lines()
+        .skip_while(|line| !line.starts_with("M  END"))
+        .skip(1);
+    while let Some(line) = lines.next() {
+        let name = line
+            .strip_prefix('>')
+            .and_then(|header| header.split_once('<'))
+            .and_then(|(_, name)| name.split_once('>'));
+        let Some((name, _)) = name else {
+            continue;
+        };
+        let value = lines
+            .by_ref()
+            .take_while(|line| !line.trim().is_empty())
+            .collect::<Vec<_>>()
+            .join("\n");
+        if let Ok(value) = value.trim().parse() {
+            properties.insert(name.to_string(), value);
+        }
+    }
+    properties
+}
+
+fn mol_bond_order(bond_type: &str) -> Result<f64> {
+    Ok(match bond_type.parse::<usize>()? {
+        order @ 1..=3 => order as f64,
+        4 => 1.5,
+        // Query bond types are read as single bonds
+        _ => 1.,
+    })
+}
+
+fn read_mol_v2000<'a>(counts: &str, mut lines: impl Iterator<Item = &'a str>) -> Result<MolTable> {
+    let atom_count: usize = column(counts, 0..3)
+        .parse()
+        .with_context(|| format!("Unable to parse atom count in counts line {counts}"))?;
+    let bond_count: usize = column(counts, 3..6)
+        .parse()
+        .with_context(|| format!("Unable to parse bond count in counts line {counts}"))?;
+    let mut atoms = (0..atom_count)
+        .map(|_| {
+            let line = lines
+                .next()
+                .with_context(|| "Count of atom lines is less than atom count")?;
+            let position = read_vector(
+                [0..10, 10..20, 20..30]
+                    .map(|range| column(line, range))
+                    .into_iter(),
+            )
+            .with_context(|| format!("Invalid atom line {line} in MOL file"))?;
+            let symbol = column(line, 31..34);
+            let element = element_symbol_to_num(symbol)
+                .with_context(|| format!("Invalid element token in {line}"))?;
+            let formal_charge = match column(line, 36..39) {
+                "" => 0,
+                code => match code.parse::<i64>()? {
+                    code @ 1..=3 => 4 - code,
+                    code @ 5..=7 => 4 - code,
+                    _ => 0,
+                },
+            };
+            Ok(Atom3D {
+                element,
+                position: Point3::from(position),
+                formal_charge: formal_charge as f64,
+                ..Default::default()
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let bonds = (0..bond_count)
+        .map(|_| {
+            let line = lines
+                .next()
+                .with_context(|| "Count of bond lines is less than bond count")?;
+            let a: usize = column(line, 0..3).parse()?;
+            let b: usize = column(line, 3..6).parse()?;
+            let bond = mol_bond_order(column(line, 6..9))
+                .with_context(|| format!("Invalid bond type in line {line}"))?;
+            if !(1..=atom_count).contains(&a) || !(1..=atom_count).contains(&b) {
+                Err(anyhow!("Atom of bond in line {line} not found"))?
+            }
+            Ok((a - 1, b - 1, bond))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    // Charges in the atom block are reset by any `M  CHG` line
+    let mut charge_reset = false;
+    while let Some(line) = lines.next() {
+        if line.starts_with("M  END") {
+            break;
+        }
+        if line.starts_with("A  ") {
+            // Atom alias takes the next line
+            lines.next();
+            continue;
+        }
+        let property = column(line, 3..6);
+        if !line.starts_with("M  ") || !["CHG", "ISO", "RAD"].contains(&property) {
+            continue;
+        }
+        if property == "CHG" && !charge_reset {
+            charge_reset = true;
+            atoms.iter_mut().for_each(|atom| atom.formal_charge = 0.);
+        }
+        let values = line[6..]
+            .split_whitespace()
+            .skip(1)
+            .map(|item| item.parse::<i64>())
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("Invalid property line {line}"))?;
+        for pair in values.chunks_exact(2) {
+            let atom = usize::try_from(pair[0] - 1)
+                .ok()
+                .and_then(|index| atoms.get_mut(index))
+                .with_context(|| format!("Atom {} in property line {line} not found", pair[0]))?;
+            match property {
+                "CHG" => atom.formal_charge = pair[1] as f64,
+                "ISO" => atom.isotope = Some(pair[1] as usize),
+                _ => {
+                    atom.tags.insert("radical".to_string(), pair[1].to_string());
+                }
+            }
+        }
+    }
+    Ok((atoms, bonds))
+}
+
+fn read_mol_v3000<'a>(lines: impl Iterator<Item = &'a str>) -> Result<MolTable> {
+    // Lines ending with `-` are continued in the next line
+    let mut v30_lines: Vec<String> = vec![];
+    let mut continued = false;
+    for line in lines {
+        if line.starts_with("M  END") {
+            break;
+        }
+        let Some(line) = line.strip_prefix("M  V30 ") else {
+            continue;
+        };
+        let (line, next_continued) = match line.trim_end().strip_suffix('-') {
+            Some(line) => (line, true),
+            None => (line.trim_end(), false),
+        };
+        match v30_lines.last_mut() {
+            Some(last) if continued => last.push_str(line),
+            _ => v30_lines.push(line.to_string()),
+        }
+        continued = next_continued;
+    }
+    let block = |name: &str| {
+        let begin = format!("BEGIN {name}");
+        let end = format!("END {name}");
+        v30_lines
+            .iter()
+            .skip_while(move |line| line.trim() != begin)
+            .skip(1)
+            .take_while(move |line| line.trim() != end)
+    };
+    let mut indexes = BTreeMap::new();
+    let atoms = block("ATOM")
+        .enumerate()
+        .map(|(index, line)| {
+            let items = line.split_whitespace().collect::<Vec<_>>();
+            if items.len() < 6 {
+                Err(anyhow!("Invalid atom line {line} in MOL file"))?
+            }
+            indexes.insert(items[0].to_string(), index);
+            let element = element_symbol_to_num(items[1])
+                .with_context(|| format!("Invalid element token in {line}"))?;
+            let position = read_vector(items[2..5].iter().copied())
+                .with_context(|| format!("Invalid atom line {line} in MOL file"))?;
+            let mut atom = Atom3D {
+                element,
+                position: Point3::from(position),
+                ..Default::default()
+            };
+            for (key, value) in items[6..].iter().filter_map(|item| item.split_once('=')) {
+                let invalid = || format!("Invalid atom property {key}={value} in line {line}");
+                match key {
+                    "CHG" => atom.formal_charge = value.parse().with_context(invalid)?,
+                    "MASS" => atom.isotope = Some(value.parse().with_context(invalid)?),
+                    "RAD" => {
+                        atom.tags.insert("radical".to_string(), value.to_string());
+                    }
+                    _ => {}
+                }
+            }
+            Ok(atom)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let bonds = block("BOND")
+        .map(|line| {
+            let items = line.split_whitespace().collect::<Vec<_>>();
+            if items.len() < 4 {
+                Err(anyhow!("Invalid bond line {line} in MOL file"))?
+            }
+            let bond = mol_bond_order(items[1])
+                .with_context(|| format!("Invalid bond type in line {line}"))?;
+            let atom = |item: &str| {
+                indexes
+                    .get(item)
+                    .copied()
+                    .with_context(|| format!("Atom {item} of bond in line {line} not found"))
+            };
+            Ok((atom(items[2])?, atom(items[3])?, bond))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok((atoms, bonds))
+}
+
+/// Lines like `M  CHG  2   1   1   3  -1` for non-zero values, 8 atoms in a line at most
+fn write_mol_property(name: &str, values: &[i64]) -> Vec<String> {
+    let values = values
+        .iter()
+        .enumerate()
+        .filter(|(_, value)| **value != 0)
+        .map(|(index, value)| format!(" {:>3} {:>3}", index + 1, value))
+        .collect::<Vec<_>>();
+    values
+        .chunks(8)
+        .map(|chunk| format!("M  {name}{:>3}{}", chunk.len(), chunk.concat()))
+        .collect()
+}
+
+#[test]
+fn mol2_atom_attributes() {
+    let content = "@<TRIPOS>MOLECULE
+NO2
+3 2 0 0 0
+SMALL
+USER_CHARGES
+
+@<TRIPOS>ATOM
+1 N1 0.0 0.0 0.0 N.pl3 1 NIT1 0.42
+2 O1 1.2 0.0 0.0 O.2 1 NIT1 -0.21
+3 O2 -0.6 1.0 0.0 O.3 1 NIT1 -0.21
+@<TRIPOS>BOND
+1 1 2 2
+2 1 3 1
+@<TRIPOS>UNITY_ATOM_ATTR
+1 1
+charge 1
+3 1
+charge -1
+";
+    let molecule = BasicIOMolecule::input("mol2", content.as_bytes()).unwrap();
+    assert_eq!(molecule.atoms[0].formal_charge, 1.);
+    assert_eq!(molecule.atoms[2].formal_charge, -1.);
+    assert_eq!(molecule.atoms[1].partial_charge, Some(-0.21));
+    assert_eq!(molecule.atoms[1].name.as_deref(), Some("O1"));
+    assert_eq!(molecule.atoms[1].residue_id, Some(1));
+    assert_eq!(molecule.atoms[1].residue_name.as_deref(), Some("NIT1"));
+    let output = molecule.output("mol2").unwrap();
+    let reloaded = BasicIOMolecule::input("mol2", output.as_bytes()).unwrap();
+    assert_eq!(reloaded.atoms, molecule.atoms);
+    assert_eq!(reloaded.bonds, molecule.bonds);
+}
+
+#[test]
+fn periodic_formats_round_trip() {
+    let mut molecule = BasicIOMolecule::new(
+        "cell".to_string(),
+        vec![
+            Atom3D {
+                element: 8,
+                position: Point3::new(1., 1., 1.),
+                ..Default::default()
+            },
+            Atom3D {
+                element: 1,
+                position: Point3::new(1.5, 1.8, 1.),
+                ..Default::default()
+            },
+            Atom3D {
+                element: 8,
+                position: Point3::new(3., 3., 3.),
+                ..Default::default()
+            },
+        ],
+        vec![],
+    );
+    molecule.lattice = Some(Lattice::new(
+        Vector3::new(5., 0., 0.),
+        Vector3::new(1., 5., 0.),
+        Vector3::new(0., 0., 6.),
+    ));
+    for format in ["extxyz", "poscar", "cp2k"] {
+        let output = molecule.output(format).unwrap();
+        let reloaded = BasicIOMolecule::input(format, output.as_bytes()).unwrap();
+        assert_eq!(reloaded.atoms, molecule.atoms);
+        assert_eq!(reloaded.lattice, molecule.lattice);
+    }
+    let poscar = "Si
+-33.75
+1 0 0
+0 1 0
+0 0 1.25
+Si_pv
+2
+Selective dynamics
+Direct
+0 0 0 T T T
+0.5 0.5 0.5 F F F
+";
+    let molecule = BasicIOMolecule::input("poscar", poscar.as_bytes()).unwrap();
+    assert_eq!(molecule.atoms[1].position, Point3::new(1.5, 1.5, 1.875));
+}
+
+#[test]
+fn mol_and_sdf_round_trip() {
+    let content = "acetate
+  lmers
+
+  4  3  0  0  0  0  0  0  0  0999 V2000
+    0.0000    0.0000    0.0000 C   0  0  0  0  0  0  0  0  0  0  0  0
+    1.5200    0.0000    0.0000 C   0  0  0  0  0  0  0  0  0  0  0  0
+    2.1400    1.0800    0.0000 O   0  0  0  0  0  0  0  0  0  0  0  0
+    2.1400   -1.0800    0.0000 O   0  5  0  0  0  0  0  0  0  0  0  0
+  1  2  1  0  0  0  0
+  2  3  2  0  0  0  0
+  2  4  1  0  0  0  0
+M  ISO  1   1  13
+M  END
+> <NAME>
+acetate
+
+$$$$
+cyanide
+  lmers
+
+  0  0  0     0  0            999 V3000
+M  V30 BEGIN CTAB
+M  V30 COUNTS 2 1 0 0 0
+M  V30 BEGIN ATOM
+M  V30 10 C 0.0 0.0 0.0 0 CHG=-1 -
+M  V30 RAD=2
+M  V30 20 N 1.1 0.0 0.0 0 CHG=1
+M  V30 END ATOM
+M  V30 BEGIN BOND
+M  V30 1 3 10 20
+M  V30 END BOND
+M  V30 END CTAB
+M  END
+> <energy>
+-92.5
+
+>  <source> (1)
+generated
+
+$$$$
+";
+    let molecules = BasicIOMolecule::input_all("sdf", content.as_bytes()).unwrap();
+    assert_eq!(molecules.len(), 2);
+    let acetate = &molecules[0];
+    assert_eq!(acetate.title, "acetate");
+    assert_eq!(acetate.atoms[3].formal_charge, -1.);
+    assert_eq!(acetate.atoms[0].isotope, Some(13));
+    assert_eq!(acetate.bonds[1], (1, 2, 2.));
+    let cyanide = &molecules[1];
+    assert_eq!(cyanide.atoms[0].formal_charge, -1.);
+    assert_eq!(cyanide.atoms[0].tags["radical"], "2");
+    assert_eq!(cyanide.bonds, vec![(0, 1, 3.)]);
+    assert_eq!(
+        cyanide.properties,
+        BTreeMap::from([("energy".to_string(), -92.5)])
+    );
+    let output = BasicIOMolecule::output_all(&molecules, "sdf").unwrap();
+    assert!(output.contains("M  END\n> <energy>\n-92.5\n\n$$$$"));
+    let reloaded = BasicIOMolecule::input_all("sdf", output.as_bytes()).unwrap();
+    assert_eq!(reloaded[1].properties, cyanide.properties);
+    assert!(reloaded[0].properties.is_empty());
+    for molecule in &molecules {
+        for format in ["sdf", "mol_v3000"] {
+            let output = molecule.output(format).unwrap();
+            let reloaded = BasicIOMolecule::input(format, output.as_bytes()).unwrap();
+            assert_eq!(reloaded.atoms, molecule.atoms);
+            assert_eq!(reloaded.bonds, molecule.bonds);
+        }
+    }
+}
+
+#[test]
+fn pdb_and_cif_residues() {
+    let content = "TITLE     SERINE FRAGMENT
+CRYST1   20.000   20.000   20.000  90.00  90.00  90.00 P 1           1
+ATOM      1  N   SER A  57      -1.200   0.400   0.000  1.00  0.00           N
+ATOM      2  CA  SER A  57       0.000   0.000   0.000  1.00  0.00           C
+ATOM      3  OG ASER A  57       0.500   1.300   0.000  0.50  0.00           O
+ATOM      4  OG BSER A  57       0.500  -1.300   0.000  0.50  0.00           O
+HETATM    5 NA    NA B   1       5.000   5.000   5.000  1.00  0.00          NA1+
+HETATM    6  O   HOH B   2       3.000   3.000   3.000  1.00  0.00
+CONECT    1    2
+CONECT    2    1    3
+END
+";
+    let molecule = BasicIOMolecule::input("pdb", content.as_bytes()).unwrap();
+    assert_eq!(molecule.title, "SERINE FRAGMENT");
+    assert_eq!(molecule.atoms.len(), 5);
+    assert_eq!(molecule.atoms[2].position.y, 1.3);
+    assert_eq!(molecule.atoms[3].element, 11);
+    assert_eq!(molecule.atoms[3].formal_charge, 1.);
+    assert_eq!(molecule.atoms[4].element, 8);
+    assert_eq!(molecule.bonds, vec![(0, 1, 1.), (1, 2, 1.)]);
+    assert_eq!(molecule.ids["A:SER57:CA"], 1);
+    assert_eq!(molecule.groups["A:SER57"], BTreeSet::from([0, 1, 2]));
+    assert_eq!(molecule.groups["B:HOH2"], BTreeSet::from([4]));
+    assert!(molecule.lattice.is_some());
+    for format in ["pdb", "cif"] {
+        let output = molecule.output(format).unwrap();
+        let reloaded = BasicIOMolecule::input(format, output.as_bytes()).unwrap();
+        assert_eq!(reloaded.atoms, molecule.atoms);
+        assert_eq!(reloaded.ids, molecule.ids);
+        assert_eq!(reloaded.groups, molecule.groups);
+        let lattice = reloaded.lattice.unwrap().0;
+        assert!((lattice[2] - molecule.lattice.unwrap().0[2]).norm() < 1e-3);
+    }
+    let structure = SparseMolecule::from(molecule);
+    let selected =
+        crate::layer::SelectMany::GroupName("A:SER57".to_string()).to_indexes(&structure);
+    assert_eq!(selected.len(), 3);
+    let mut carbonyl = BasicIOMolecule::new(
+        "carbonyl".to_string(),
+        [6, 8]
+            .map(|element| Atom3D {
+                element,
+                residue_name: Some("UNL1".to_string()),
+                ..Default::default()
+            })
+            .to_vec(),
+        vec![(0, 1, 2.)],
+    );
+    let output = carbonyl.output("pdb").unwrap();
+    assert!(output.contains("CONECT    1    2    2"));
+    let reloaded = BasicIOMolecule::input("pdb", output.as_bytes()).unwrap();
+    assert_eq!(reloaded.bonds, vec![(0, 1, 2.)]);
+    assert_eq!(reloaded.atoms[0].residue_name.as_deref(), Some("UNL"));
+    carbonyl.atoms[0].residue_id = Some(10000);
+    assert!(carbonyl.output("pdb").is_err());
+    let content = "MODEL        1
+ATOM      1  O   HOH A   1       0.000   0.000   0.000
+ENDMDL
+MODEL        2
+ATOM      1  O   HOH A   1       1.000   0.000   0.000
+ENDMDL
+";
+    assert!(BasicIOMolecule::input("pdb", content.as_bytes()).is_err());
+    let models = BasicIOMolecule::input_all("pdb", content.as_bytes()).unwrap();
+    assert_eq!(models.len(), 2);
+    assert_eq!(models[1].atoms[0].position.x, 1.);
+}
+
+#[test]
+fn gaussian_input_and_log() {
+    let mut molecule = BasicIOMolecule::new(
+        "water".to_string(),
+        vec![
+            Atom3D {
+                element: 8,
+                ..Default::default()
+            },
+            Atom3D {
+                element: 1,
+                position: Point3::new(0.96, 0., 0.),
+                ..Default::default()
+            },
+            Atom3D {
+                element: 1,
+                position: Point3::new(-0.24, 0.93, 0.),
+                hidden: true,
+                ..Default::default()
+            },
+        ],
+        vec![],
+    );
+    molecule.ids = BTreeMap::from([("O".to_string(), 0), ("H1".to_string(), 1)]);
+    let options = GaussianOptions {
+        link0: vec!["%nprocshared=4".to_string()],
+        route: "#p opt=modredundant b3lyp/def2svp".to_string(),
+        constraints: vec![
+            vec!["O".to_string(), "H1".to_string()],
+            vec!["2".to_string()],
+        ],
+        link1: vec!["#p freq b3lyp/def2svp geom=check guess=read".to_string()],
+    };
+    let input = molecule.output_to_gaussian(&options).unwrap();
+    let lines = input.lines().collect::<Vec<_>>();
+    assert_eq!(lines[0], "%chk=lmers.chk");
+    // The ghost hydrogen has no electrons, leaving a hydroxyl radical
+    assert_eq!(lines[6], "0 2");
+    assert!(lines[9].starts_with("H-Bq"));
+    assert_eq!(&lines[11..13], ["B 1 2 F", "X 3 F"]);
+    assert_eq!(lines[14], "--Link1--");
+    assert!(molecule
+        .output_to_gaussian(&GaussianOptions {
+            constraints: vec![vec!["H2".to_string()]],
+            ..Default::default()
+        })
+        .is_err());
+    let log = " Charge =  0 Multiplicity = 1
+                          Input orientation:
+ ---------------------------------------------------------------------
+ Center     Atomic      Atomic             Coordinates (Angstroms)
+ Number     Number       Type             X           Y           Z
+ ---------------------------------------------------------------------
+      1          8           0        0.000000    0.000000    0.000000
+      2          1           0        0.980000    0.000000    0.000000
+ ---------------------------------------------------------------------
+ SCF Done:  E(RB3LYP) =  -76.3000000000     A.U. after   10 cycles
+ Berny optimization.
+                          Input orientation:
+ ---------------------------------------------------------------------
+ Center     Atomic      Atomic             Coordinates (Angstroms)
+ Number     Number       Type             X           Y           Z
+ ---------------------------------------------------------------------
+      1          8           0        0.000000    0.000000    0.000000
+      2          1           0        0.960000    0.000000    0.000000
+ ---------------------------------------------------------------------
+ SCF Done:  E(RB3LYP) =  -76.4000000000     A.U. after    8 cycles
+    Optimization completed.
+ Sum of electronic and thermal Free Energies=         -76.390000
+ Normal termination of Gaussian 16 at Mon Jan  1 00:00:00 2024.
+";
+    assert!(BasicIOMolecule::input("gaussian", log.as_bytes()).is_err());
+    let last = BasicIOMolecule::input_frame("gaussian", log.as_bytes(), Frame::Last).unwrap();
+    assert_eq!(last.atoms[1].position.x, 0.96);
+    assert_eq!(last.properties["scf_energy"], -76.4);
+    assert_eq!(last.properties["free_energy"], -76.39);
+    assert_eq!(last.properties["converged"], 1.);
+    assert_eq!(last.properties["normal_termination"], 1.);
+    assert_eq!((last.charge, last.multiplicity), (Some(0), Some(1)));
+    let first = BasicIOMolecule::input_frame("gaussian", log.as_bytes(), Frame::First).unwrap();
+    assert_eq!(first.atoms[1].position.x, 0.98);
+    assert_eq!(first.properties["scf_energy"], -76.3);
+}
+
+#[test]
+fn orca_input_and_output() {
+    let mut molecule = BasicIOMolecule::new(
+        "water".to_string(),
+        vec![
+            Atom3D {
+                element: 8,
+                ..Default::default()
+            },
+            Atom3D {
+                element: 1,
+                position: Point3::new(0.96, 0., 0.),
+                isotope: Some(2),
+                ..Default::default()
+            },
+        ],
+        vec![],
+    );
+    molecule.ids = BTreeMap::from([("O".to_string(), 0), ("H1".to_string(), 1)]);
+    molecule.charge = Some(-1);
+    let options = OrcaOptions {
+        keywords: "! Opt B3LYP def2-SVP".to_string(),
+        blocks: vec!["%pal nprocs 4 end".to_string()],
+        constraints: vec![vec!["O".to_string(), "H1".to_string()]],
+    };
+    let input = molecule.output_to_orca(&options).unwrap();
+    let lines = input.lines().collect::<Vec<_>>();
+    assert_eq!(lines[0], "! Opt B3LYP def2-SVP");
+    assert_eq!(lines[4], "    { B 0 1 C }");
+    assert_eq!(lines[7], "* xyz -1 1");
+    assert!(lines[9].ends_with("M = 2"));
+    assert_eq!(lines[10], "*");
+    let output = "
+Total Charge           Charge          ....    0
+Multiplicity           Mult            ....    1
+                        *****************************
+                        * Geometry Optimization Run *
+                        *****************************
+        *************************************************************
+        *                GEOMETRY OPTIMIZATION CYCLE   1            *
+        *************************************************************
+---------------------------------
+CARTESIAN COORDINATES (ANGSTROEM)
+---------------------------------
+  O      0.000000    0.000000    0.000000
+  H      0.980000    0.000000    0.000000
+
+FINAL SINGLE POINT ENERGY       -76.300000000000
+                    ***********************HURRAY********************
+                    ***        THE OPTIMIZATION HAS CONVERGED     ***
+---------------------------------
+CARTESIAN COORDINATES (ANGSTROEM)
+---------------------------------
+  O      0.000000    0.000000    0.000000
+  H      0.960000    0.000000    0.000000
+
+FINAL SINGLE POINT ENERGY       -76.400000000000
+Final Gibbs free energy         ...    -76.39000000 Eh
+                             ****ORCA TERMINATED NORMALLY****
+";
+    let last = BasicIOMolecule::input_frame("orca", output.as_bytes(), Frame::Last).unwrap();
+    assert_eq!(last.atoms[1].position.x, 0.96);
+    assert_eq!(last.properties["energy"], -76.4);
+    assert_eq!(last.properties["free_energy"], -76.39);
+    assert_eq!(last.properties["converged"], 1.);
+    assert_eq!(last.properties["normal_termination"], 1.);
+    assert_eq!((last.charge, last.multiplicity), (Some(0), Some(1)));
+    let first = BasicIOMolecule::input_frame("orca", output.as_bytes(), Frame::Index(0)).unwrap();
+    assert_eq!(first.properties["energy"], -76.3);
+    let xyz = "2\nCoordinates from ORCA-job job E -76.400000000000\nO 0 0 0\nH 0.96 0 0\n";
+    let molecule = BasicIOMolecule::input("orca", xyz.as_bytes()).unwrap();
+    assert_eq!(molecule.properties["energy"], -76.4);
+    assert_eq!(molecule.atoms.len(), 2);
+}
+
+#[test]
+fn multiple_frame_xyz() {
+    let trajectory = "3
+ energy: -5.070544440612 gnorm: 0.000445184160 xtb: 6.6.1 (8d0f1dd)
+O 0 0 0
+H 0.96 0 0
+H -0.24 0.93 0
+
+3
+ energy: -5.085 gnorm: 0.0001
+O 0 0 0
+H 0.95 0 0
+H -0.24 0.92 0
+3
+-5.075
+O 0 0 0
+H 0.97 0 0
+H -0.24 0.94 0
+";
+    let frames = BasicIOMolecule::input_all("xyz", trajectory.as_bytes()).unwrap();
+    assert_eq!(frames.len(), 3);
+    assert_eq!(frames[2].properties["energy"], -5.075);
+    assert!(BasicIOMolecule::input("xyz", trajectory.as_bytes()).is_err());
+    let first = BasicIOMolecule::input_frame("xyz", trajectory.as_bytes(), Frame::First).unwrap();
+    assert_eq!(first.atoms[1].position.x, 0.96);
+    let lowest =
+        BasicIOMolecule::input_frame("xyz", trajectory.as_bytes(), Frame::LowestEnergy).unwrap();
+    assert_eq!(lowest.atoms[1].position.x, 0.95);
+    assert!(BasicIOMolecule::input_frame("xyz", trajectory.as_bytes(), Frame::All).is_err());
+    let output = BasicIOMolecule::output_all(&frames, "xyz").unwrap();
+    let frames = BasicIOMolecule::input_all("xyz", output.as_bytes()).unwrap();
+    assert_eq!(frames.len(), 3);
+    assert_eq!(frames[1].properties["energy"], -5.085);
+    assert_eq!(
+        comment_energy("Properties=species:S:1:pos:R:3 energy=-76.4"),
+        Some(-76.4)
+    );
+    assert!(BasicIOMolecule::input_all("xyz", "3\n\nO 0 0 0\nH 1 0 0\n".as_bytes()).is_err());
+    assert!(BasicIOMolecule::output_all(&[first], "pdb").is_err());
+}
@@ -0,0 +1,118 @@
+use bincode::{Decode, Encode};
+use nalgebra::{Matrix3, Point3, Vector3};
+use serde::{Deserialize, Serialize};
+
+/// Lattice vectors `a`, `b` and `c` of a periodic structure in Angstrom.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Encode, Decode)]
+pub struct Lattice(#[bincode(with_serde)] pub [Vector3<f64>; 3]);
+
+impl Lattice {
+    pub fn new(a: Vector3<f64>, b: Vector3<f64>, c: Vector3<f64>) -> Self {
+        Self([a, b, c])
+    }
+
+    /// Create an orthorhombic cell from the lengths of the lattice vectors
+    pub fn orthorhombic(a: f64, b: f64, c: f64) -> Self {
+        Self::new(Vector3::x() * a, Vector3::y() * b, Vector3::z() * c)
+    }
+
+    /// Create a cell from lengths and angles in degree, with `a` along x and `b` in the xy plane
+    pub fn from_parameters(a: f64, b: f64, c: f64, alpha: f64, beta: f64, gamma: f64) -> Self {
+        let (alpha, beta, gamma) = (alpha.to_radians(), beta.to_radians(), gamma.to_radians());
+        let cx = beta.cos();
+        let cy = (alpha.cos() - beta.cos() * gamma.cos()) / gamma.sin();
+        let cz = (1. - cx.powi(2) - cy.powi(2)).max(0.).sqrt();
+        Self::new(
+            Vector3::new(a, 0., 0.),
+            Vector3::new(b * gamma.cos(), b * gamma.sin(), 0.),
+            Vector3::new(cx, cy, cz) * c,
+        )
+    }
+
+    /// Lengths and angles in degree of the cell, the inverse of `from_parameters`
+    pub fn parameters(&self) -> (f64, f64, f64, f64, f64, f64) {
+        let [a, b, c] = self.0;
+        (
+            a.norm(),
+            b.norm(),
+            c.norm(),
+            b.angle(&c).to_degrees(),
+            a.angle(&c).to_degrees(),
+            a.angle(&b).to_degrees(),
+        )
+    }
+
+    pub fn a(&self) -> Vector3<f64> {
+        self.0[0]
+    }
+
+    pub fn b(&self) -> Vector3<f64> {
+        self.0[1]
+    }
+
+    pub fn c(&self) -> Vector3<f64> {
+        self.0[2]
+    }
+
+    /// The matrix with lattice vectors as columns, converting fractional coordinates to cartesian ones
+    pub fn matrix(&self) -> Matrix3<f64> {
+        Matrix3::from_columns(&self.0)
+    }
+
+    pub fn volume(&self) -> f64 {
+        self.matrix().determinant().abs()
+    }
+
+    pub fn to_fractional(&self, vector: Vector3<f64>) -> Option<Vector3<f64>> {
+        Some(self.matrix().try_inverse()? * vector)
+    }
+
+    pub fn to_cartesian(&self, fractional: Vector3<f64>) -> Vector3<f64> {
+        self.matrix() * fractional
+    }
+
+    /// Move the position into the cell spanned by the lattice vectors from the origin
+    pub fn wrap(&self, position: Point3<f64>) -> Point3<f64> {
+        match self.to_fractional(position.coords) {
+            Some(fractional) => {
+                Point3::from(self.to_cartesian(fractional.map(|value| value - value.floor())))
+            }
+            None => position,
+        }
+    }
+
+    /// The shortest periodic image of a displacement vector
+    pub fn minimum_image(&self, vector: Vector3<f64>) -> Vector3<f64> {
+        let Some(fractional) = self.to_fractional(vector) else {
+            return vector;
+        };
+        let reduced = self.to_cartesian(fractional.map(|value| value - value.round()));
+        // For skewed cells the nearest image may be in the neighbor cells of the reduced one
+        let mut shortest = reduced;
+        for i in -1..=1 {
+            for j in -1..=1 {
+                for k in -1..=1 {
+                    let image =
+                        reduced + self.to_cartesian(Vector3::new(i as f64, j as f64, k as f64));
+                    if image.norm() < shortest.norm() {
+                        shortest = image;
+                    }
+                }
+            }
+        }
+        shortest
+    }
+}
+
+#[test]
+fn minimum_image_in_skewed_cell() {
+    let lattice = Lattice::new(
+        Vector3::new(4., 0., 0.),
+        Vector3::new(2., 3., 0.),
+        Vector3::new(0., 0., 5.),
+    );
+    let image = lattice.minimum_image(Vector3::new(3.5, 0., 4.));
+    assert!((image - Vector3::new(-0.5, 0., -1.)).norm() < 1e-10);
+    let wrapped = lattice.wrap(Point3::new(-1., 1., 6.));
+    assert!((wrapped - Point3::new(3., 1., 1.)).norm() < 1e-10);
+}
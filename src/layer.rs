@@ -6,15 +6,23 @@ use std::{
 };
 
 use bincode::{Decode, Encode};
-use nalgebra::{Isometry3, Point3, Translation3, Vector3};
+use nalgebra::{Isometry3, Point3, Translation3, Unit, UnitQuaternion, Vector3};
 use redb::Value;
-use serde::{Deserialize, Serialize};
+use serde::{ser::SerializeMap, Deserialize, Serialize};
 
 use crate::{
     chemistry::Atom3D,
+    elements::{ElementClass, RadiiSet},
     group_name::GroupName,
+    lattice::Lattice,
+    selector::{parse_select_many, parse_select_one},
     sparse_molecule::{SparseAtomList, SparseMolecule},
-    utils::geometric::axis_angle_for_b2a,
+    utils::{
+        geometric::{angle, axis_angle_for_b2a, dihedral, kabsch, perpendicular},
+        hydrogens::{electron_domains, free_directions, missing_hydrogens_by_bonds},
+        perception::perceive_bonds,
+        smarts::Pattern,
+    },
 };
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Encode, Decode)]
@@ -123,6 +131,161 @@ pub enum Layer {
     UnHide {
         select: SelectMany,
     },
+    /// Set the lattice of a periodic structure, or remove it by `null`
+    SetLattice {
+        #[serde(default)]
+        lattice: Option<Lattice>,
+    },
+    /// Wrap selected atoms into the unit cell, appended after moving layers to wrap their results
+    Wrap {
+        #[serde(default)]
+        select: SelectMany,
+    },
+    /// Perceive bonds, bond orders and formal charges of selected atoms from coordinates,
+    /// bonds between selected atoms are replaced and bonds to other atoms are kept. Rings
+    /// of 5 to 7 atoms following the Hückel rule get aromatic bonds of order 1.5.
+    PerceiveBonds {
+        #[serde(default)]
+        select: SelectMany,
+    },
+    /// Set total charge and spin multiplicity of the molecule, omitted values are kept
+    SetCharge {
+        #[serde(default)]
+        charge: Option<i64>,
+        #[serde(default)]
+        multiplicity: Option<usize>,
+    },
+    /// Change total charge and spin multiplicity from current values, like
+    /// `{ charge: -1 }` after removing a proton
+    AdjustCharge {
+        #[serde(default)]
+        charge: i64,
+        #[serde(default)]
+        multiplicity: i64,
+    },
+    /// Set the dihedral angle a-b-c-d by rotating the atoms on the `c` side of the b-c bond
+    SetDihedral {
+        a: SelectOne,
+        b: SelectOne,
+        c: SelectOne,
+        d: SelectOne,
+        value: f64,
+        #[serde(default)]
+        degree: bool,
+    },
+    /// Set the distance a-b by moving the atoms on the `b` side of the a-b bond,
+    /// or the atoms in `select` if given
+    SetDistance {
+        a: SelectOne,
+        b: SelectOne,
+        value: f64,
+        #[serde(default)]
+        select: Option<SelectMany>,
+    },
+    /// Set the angle a-b-c by rotating the atoms on the `c` side of the b-c bond around b,
+    /// or the atoms in `select` if given
+    SetAngle {
+        a: SelectOne,
+        b: SelectOne,
+        c: SelectOne,
+        value: f64,
+        #[serde(default)]
+        degree: bool,
+        #[serde(default)]
+        select: Option<SelectMany>,
+    },
+    /// Replace the `replace` atom bonded to `center` with a substituent. Atom 0 of `data` is
+    /// the dummy atom standing for `center`, and atom 1 is the atom bonded to `center`, which
+    /// takes the index of `replace`. The new bond is scaled to the sum of covalent radii.
+    /// Ids and groups of the substituent are prefixed with `name`, and the new atoms are put
+    /// in the group `name`.
+    Substitute {
+        center: SelectOne,
+        replace: SelectOne,
+        name: String,
+        data: SparseMolecule,
+    },
+    /// Fill open valences of the selected atoms with hydrogens, placed by the number of
+    /// electron domains from bond orders. If `name` is given, new hydrogens are put in the
+    /// group `name` with ids like `{name}_{parent}_{n}`, where `parent` is an id of the
+    /// bonded atom or its index, and `n` counts from 1.
+    AddHydrogens {
+        #[serde(default)]
+        select: SelectMany,
+        #[serde(default)]
+        name: Option<String>,
+    },
+    /// Remove the selected hydrogens, hydrogens bonded to atoms other than carbon are kept
+    /// if `keep_polar` is set
+    RemoveHydrogens {
+        #[serde(default)]
+        select: SelectMany,
+        #[serde(default)]
+        keep_polar: bool,
+    },
+    /// Superpose the selected atoms onto the reference positions by least squares, and
+    /// move the atoms in `apply` with the same isometry
+    AlignTo {
+        select: SelectMany,
+        reference: AlignReference,
+        #[serde(default)]
+        apply: SelectMany,
+    },
+    /// Renumber atoms densely, dropping empty slots and atoms removed by `RemoveAtoms`
+    Compact,
+    /// Put the atoms of each selection first in turn, the other atoms follow in the original
+    /// order. Atoms selected more than once take the first position.
+    Reorder {
+        order: Vec<SelectMany>,
+    },
+    RemoveGroups {
+        groups: Vec<String>,
+    },
+    /// Rename groups like `{ old: new }`, atoms are merged into the new group if it exists
+    RenameGroups {
+        groups: BTreeMap<String, String>,
+    },
+    RemoveIds {
+        ids: Vec<String>,
+    },
+    /// Replace the group `name` with the atoms in all of `groups`
+    GroupIntersection {
+        name: String,
+        groups: Vec<String>,
+    },
+    /// Replace the group `name` with the atoms in `group` but not in any of `excludes`
+    GroupDifference {
+        name: String,
+        group: String,
+        excludes: Vec<String>,
+    },
+}
+
+/// Reference positions of the `AlignTo` layer
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Encode, Decode)]
+#[serde(untagged)]
+#[allow(clippy::large_enum_variant)]
+pub enum AlignReference {
+    /// Positions matched to the selected atoms in the order of indexes
+    Positions(#[bincode(with_serde)] Vec<Point3<f64>>),
+    /// A structure given inline or by a file path. Atoms are matched by ids of the selected
+    /// atoms if `by_ids` is set, otherwise by indexes.
+    Structure {
+        structure: SparseMolecule,
+        #[serde(default)]
+        by_ids: bool,
+    },
+    /// `base`, atoms with the same ids in the base structure of the workflow. It is resolved
+    /// to `IdPositions` by `AppendLayers` and `DistributeLayers` runners.
+    Base(BaseReference),
+    /// Positions of atoms by ids, matched to the selected atoms with the same ids
+    IdPositions(#[bincode(with_serde)] BTreeMap<String, Point3<f64>>),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Encode, Decode)]
+#[serde(rename_all = "snake_case")]
+pub enum BaseReference {
+    Base,
 }
 
 impl Default for Layer {
@@ -134,6 +297,36 @@ impl Default for Layer {
 }
 
 impl Layer {
+    /// Resolve references to the base structure of the workflow, which is not available
+    /// when the layer is applied. Only the positions of the selected atoms with ids are kept.
+    pub fn with_base(self, base: &SparseMolecule) -> Self {
+        match self {
+            Self::AlignTo {
+                select,
+                reference: AlignReference::Base(_),
+                apply,
+            } => {
+                let selected = select.to_indexes(base);
+                let positions = base
+                    .ids
+                    .iter()
+                    .flatten()
+                    .filter(|(_, index)| selected.contains(index))
+                    .filter_map(|(id, index)| {
+                        let atom = base.atoms.read_atom(*index)?;
+                        Some((id.clone(), atom.position))
+                    })
+                    .collect();
+                Self::AlignTo {
+                    select,
+                    reference: AlignReference::IdPositions(positions),
+                    apply,
+                }
+            }
+            layer => layer,
+        }
+    }
+
     pub fn filter(&self, mut current: SparseMolecule) -> Result<SparseMolecule, LayerStorageError> {
         match self {
             Self::Transparent => {}
@@ -160,7 +353,10 @@ impl Layer {
                 }
                 .filter(molecule)?;
                 let molecule = molecule.offset(current.len());
+                let (charge, multiplicity) = combined_charge(&current, &molecule);
                 current.migrate(molecule);
+                current.charge = charge;
+                current.multiplicity = multiplicity;
             }
             Self::SetBond { bonds } => {
                 for (a, b, bond) in bonds {
@@ -184,7 +380,7 @@ impl Layer {
             Self::AppendAtoms { atoms } => {
                 current.atoms.set_atoms(
                     current.atoms.len(),
-                    atoms.iter().map(|atom| Some(*atom)).collect(),
+                    atoms.iter().cloned().map(Some).collect(),
                 );
             }
             Self::IdMap(data) => {
@@ -346,7 +542,7 @@ impl Layer {
                         .enumerate()
                         .map(|(idx, atom)| {
                             if selected.contains(&idx) {
-                                atom.map(|atom| {
+                                atom.clone().map(|atom| {
                                     let center_atom = atom.position - center;
                                     let projection = center_atom.dot(&law_vector) * law_vector;
                                     let updated_position = atom.position - 2. * projection;
@@ -356,7 +552,7 @@ impl Layer {
                                     }
                                 })
                             } else {
-                                *atom
+                                atom.clone()
                             }
                         })
                         .collect::<Vec<_>>(),
@@ -378,25 +574,27 @@ impl Layer {
                 );
                 current.atoms.migrate(atoms);
             }
-            Self::Hide { select } => {
+            Self::Hide { select } | Self::UnHide { select } => {
+                let hidden = matches!(self, Self::Hide { .. });
                 let selected = select.to_indexes(&current);
-                let atoms = current.atoms.data().iter().enumerate().map(|(idx, atom)| {
-                    if selected.contains(&idx) {
-                        Ok(if let Some(atom) = atom {
-                            Some(Atom3D {
-                                element: atom.element.checked_add(128).ok_or((idx, atom.element))?,
-                                ..*atom
-                            })
+                let atoms = current
+                    .atoms
+                    .data()
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, atom)| {
+                        if selected.contains(&idx) {
+                            atom.clone().map(|atom| Atom3D { hidden, ..atom })
                         } else {
                             None
-                        })
-                    } else {
-                        Ok(None)
-                    }
-                }).collect::<Result<Vec<_>,LayerStorageError>>()?;
+                        }
+                    })
+                    .collect::<Vec<_>>();
                 current.atoms.migrate(SparseAtomList::from(atoms));
             }
-            Self::UnHide { select } => {
+            Self::SetLattice { lattice } => current.lattice = *lattice,
+            Self::Wrap { select } => {
+                let lattice = current.lattice.ok_or(LayerStorageError::LatticeNotFound)?;
                 let selected = select.to_indexes(&current);
                 let atoms = current
                     .atoms
@@ -405,35 +603,513 @@ impl Layer {
                     .enumerate()
                     .map(|(idx, atom)| {
                         if selected.contains(&idx) {
-                            Ok(if let Some(atom) = atom {
-                                Some(Atom3D {
-                                    element: atom
-                                        .element
-                                        .checked_sub(128)
-                                        .ok_or((idx, atom.element))?,
-                                    ..*atom
-                                })
-                            } else {
-                                None
+                            atom.clone().map(|atom| Atom3D {
+                                position: lattice.wrap(atom.position),
+                                ..atom
                             })
                         } else {
-                            Ok(None)
+                            None
                         }
                     })
-                    .collect::<Result<Vec<_>, LayerStorageError>>()?;
-
+                    .collect::<Vec<_>>();
+                current.atoms.migrate(SparseAtomList::from(atoms));
+            }
+            Self::PerceiveBonds { select } => {
+                let selected = select
+                    .to_indexes(&current)
+                    .into_iter()
+                    .filter_map(|index| Some((index, current.atoms.read_atom(index)?)))
+                    .filter(|(_, atom)| atom.is_present())
+                    .collect::<Vec<_>>();
+                let atoms = selected
+                    .iter()
+                    .map(|(_, atom)| atom.clone())
+                    .collect::<Vec<_>>();
+                let (bonds, charges) = perceive_bonds(&atoms);
+                let indexes = selected
+                    .iter()
+                    .map(|(index, _)| *index)
+                    .collect::<BTreeSet<_>>();
+                let replaced = current
+                    .bonds
+                    .iter()
+                    .filter(|(a, b, _)| indexes.contains(a) && indexes.contains(b))
+                    .collect::<Vec<_>>();
+                for (a, b, _) in replaced {
+                    current.bonds.set_bond(a, b, Some(0.));
+                }
+                for (a, b, bond) in bonds {
+                    current
+                        .bonds
+                        .set_bond(selected[a].0, selected[b].0, Some(bond));
+                }
+                let atoms = selected
+                    .into_iter()
+                    .zip(charges)
+                    .map(|((index, atom), formal_charge)| {
+                        (
+                            index,
+                            Atom3D {
+                                formal_charge,
+                                ..atom
+                            },
+                        )
+                    })
+                    .collect::<BTreeMap<_, _>>();
+                let atoms = (0..current.len())
+                    .map(|index| atoms.get(&index).cloned())
+                    .collect::<Vec<_>>();
                 current.atoms.migrate(SparseAtomList::from(atoms));
             }
+            Self::SetCharge {
+                charge,
+                multiplicity,
+            } => {
+                current.charge = charge.or(current.charge);
+                current.multiplicity = multiplicity.or(current.multiplicity);
+            }
+            Self::AdjustCharge {
+                charge,
+                multiplicity,
+            } => {
+                let updated = current.spin_multiplicity() as i64 + multiplicity;
+                if updated < 1 {
+                    Err(LayerStorageError::InvalidMultiplicity(updated))?
+                }
+                current.charge = Some(current.total_charge() + charge);
+                current.multiplicity = Some(updated as usize);
+            }
+            Self::SetDihedral {
+                a,
+                b,
+                c,
+                d,
+                value,
+                degree,
+            } => {
+                let [a, b, c, d] =
+                    [a, b, c, d].map(|select| select.to_index(&current).ok_or(select.clone()));
+                let (a, b, c, d) = (a?, b?, c?, d?);
+                let moved = moved_atoms(&current, b, c, &None)?;
+                let [pa, pb, pc, pd] = [a, b, c, d].map(|index| atom_position(&current, index));
+                let target = if *degree { value * PI / 180. } else { *value };
+                let rotation = UnitQuaternion::from_axis_angle(
+                    &Unit::new_normalize(pc - pb),
+                    target - dihedral(pa, pb, pc, pd),
+                );
+                current
+                    .atoms
+                    .isometry(Isometry3::rotation_wrt_point(rotation, pc), &moved);
+            }
+            Self::SetDistance {
+                a,
+                b,
+                value,
+                select,
+            } => {
+                let [a, b] = [a, b].map(|select| select.to_index(&current).ok_or(select.clone()));
+                let (a, b) = (a?, b?);
+                let moved = moved_atoms(&current, a, b, select)?;
+                let [pa, pb] = [a, b].map(|index| atom_position(&current, index));
+                let direction = pb - pa;
+                let direction = if direction.norm() == 0. {
+                    Vector3::x()
+                } else {
+                    direction.normalize()
+                };
+                let translation = direction * *value - (pb - pa);
+                current.atoms.isometry(
+                    Isometry3::translation(translation.x, translation.y, translation.z),
+                    &moved,
+                );
+            }
+            Self::SetAngle {
+                a,
+                b,
+                c,
+                value,
+                degree,
+                select,
+            } => {
+                let [a, b, c] =
+                    [a, b, c].map(|select| select.to_index(&current).ok_or(select.clone()));
+                let (a, b, c) = (a?, b?, c?);
+                let moved = moved_atoms(&current, b, c, select)?;
+                let [pa, pb, pc] = [a, b, c].map(|index| atom_position(&current, index));
+                let (ba, bc) = (pa - pb, pc - pb);
+                // Any axis perpendicular to b-a works for linear angles
+                let axis = ba.cross(&bc);
+                let axis = if axis.norm() > 1e-8 {
+                    Unit::new_normalize(axis)
+                } else {
+                    perpendicular(ba)
+                };
+                let target = if *degree { value * PI / 180. } else { *value };
+                let rotation = UnitQuaternion::from_axis_angle(&axis, target - angle(pa, pb, pc));
+                current
+                    .atoms
+                    .isometry(Isometry3::rotation_wrt_point(rotation, pb), &moved);
+            }
+            Self::Substitute {
+                center,
+                replace,
+                name,
+                data,
+            } => {
+                let center = center.to_index(&current).ok_or(center.clone())?;
+                let replace = replace.to_index(&current).ok_or(replace.clone())?;
+                let dummy = data.atoms.read_atom(0).ok_or(SelectOne::Index(0))?;
+                let attached = data.atoms.read_atom(1).ok_or(SelectOne::Index(1))?;
+                let (pc, pr) = (
+                    atom_position(&current, center),
+                    atom_position(&current, replace),
+                );
+                let center_element = current
+                    .atoms
+                    .read_atom(center)
+                    .map(|atom| atom.element)
+                    .unwrap_or_default();
+                let length = RadiiSet::Covalent
+                    .radius(center_element)
+                    .zip(RadiiSet::Covalent.radius(attached.element))
+                    .map(|(a, b)| a + b)
+                    .unwrap_or((attached.position - dummy.position).norm());
+                let direction = pr - pc;
+                let direction = if direction.norm() == 0. {
+                    Vector3::x()
+                } else {
+                    direction.normalize()
+                };
+                let rotation = UnitQuaternion::rotation_between(
+                    &(attached.position - dummy.position),
+                    &direction,
+                )
+                .unwrap_or_else(|| UnitQuaternion::from_axis_angle(&perpendicular(direction), PI));
+                let isometry = Translation3::from(pc + direction * length)
+                    * rotation
+                    * Translation3::from(-attached.position.coords);
+                // The dummy atom is dropped, the attached atom takes the place of `replace`
+                // and the other atoms are appended
+                let offset = current.len();
+                let index_map = |index: usize| match index {
+                    0 => None,
+                    1 => Some(replace),
+                    _ => Some(offset + index - 2),
+                };
+                let mut atoms = data.atoms.data().clone();
+                for atom in atoms.iter_mut().flatten() {
+                    atom.position = isometry * atom.position;
+                }
+                current.atoms.set_atoms(replace, vec![atoms[1].clone()]);
+                current.atoms.set_atoms(offset, atoms.split_off(2));
+                for a in 1..data.len() {
+                    for (b, bond) in data.bonds.get_neighbors(a).filter(|(b, _)| *b > a) {
+                        if let (Some(a), Some(b)) = (index_map(a), index_map(b)) {
+                            current.bonds.set_bond(a, b, Some(bond));
+                        }
+                    }
+                }
+                let ids = data.ids.iter().flatten().filter_map(|(id, index)| {
+                    Some((format!("{}_{}", name, id), index_map(*index)?))
+                });
+                current.ids.get_or_insert_with(BTreeMap::new).extend(ids);
+                let groups = current.groups.get_or_insert_with(GroupName::new);
+                for (group, index) in data.groups.clone().into_iter().flatten() {
+                    if let Some(index) = index_map(index) {
+                        groups.insert(format!("{}_{}", name, group), index);
+                    }
+                }
+                for index in (1..data.len()).filter_map(index_map) {
+                    groups.insert(name.to_string(), index);
+                }
+            }
+            Self::AddHydrogens { select, name } => {
+                let length = RadiiSet::Covalent.radius(1).unwrap_or_default();
+                let mut hydrogens = vec![];
+                for center in select.to_indexes(&current) {
+                    let Some(atom) = current
+                        .atoms
+                        .read_atom(center)
+                        .filter(|atom| atom.is_present())
+                    else {
+                        continue;
+                    };
+                    let (neighbors, orders): (Vec<_>, Vec<_>) = current
+                        .bonds
+                        .get_neighbors(center)
+                        .filter(|(index, bond)| {
+                            *bond != 0.
+                                && current
+                                    .atoms
+                                    .read_atom(*index)
+                                    .is_some_and(|atom| atom.is_present())
+                        })
+                        .unzip();
+                    let missing =
+                        missing_hydrogens_by_bonds(atom.element, &orders, atom.formal_charge);
+                    if missing == 0 {
+                        continue;
+                    }
+                    let vectors = neighbors
+                        .iter()
+                        .filter_map(|index| current.displacement(center, *index))
+                        // Neighbors on top of the atom give no direction
+                        .filter_map(|vector| vector.try_normalize(1e-8))
+                        .collect::<Vec<_>>();
+                    let reference = neighbors.first().and_then(|first| {
+                        current
+                            .bonded_atoms(*first)
+                            .find(|index| *index != center)
+                            .and_then(|index| current.displacement(*first, index))
+                    });
+                    let length =
+                        length + RadiiSet::Covalent.radius(atom.element).unwrap_or_default();
+                    let directions =
+                        free_directions(&vectors, reference, electron_domains(&orders));
+                    for direction in directions.into_iter().take(missing) {
+                        hydrogens.push((center, atom.position + direction * length));
+                    }
+                }
+                let offset = current.len();
+                current.atoms.set_atoms(
+                    offset,
+                    hydrogens
+                        .iter()
+                        .map(|(_, position)| {
+                            Some(Atom3D {
+                                element: 1,
+                                position: *position,
+                                ..Default::default()
+                            })
+                        })
+                        .collect(),
+                );
+                let mut counts = BTreeMap::new();
+                for (index, (parent, _)) in hydrogens.into_iter().enumerate() {
+                    current.bonds.set_bond(parent, offset + index, Some(1.));
+                    if let Some(name) = name {
+                        let count = counts.entry(parent).or_insert(0);
+                        *count += 1;
+                        let parent_name = current
+                            .ids
+                            .iter()
+                            .flatten()
+                            .find(|(_, index)| **index == parent)
+                            .map(|(id, _)| id.to_string())
+                            .unwrap_or(parent.to_string());
+                        current.ids.get_or_insert_with(BTreeMap::new).insert(
+                            format!("{}_{}_{}", name, parent_name, count),
+                            offset + index,
+                        );
+                        current
+                            .groups
+                            .get_or_insert_with(GroupName::new)
+                            .insert(name.to_string(), offset + index);
+                    }
+                }
+            }
+            Self::RemoveHydrogens { select, keep_polar } => {
+                let removed = select
+                    .to_indexes(&current)
+                    .into_iter()
+                    .filter(|index| {
+                        current
+                            .atoms
+                            .read_atom(*index)
+                            .is_some_and(|atom| atom.element == 1)
+                            && !(*keep_polar
+                                && current.bonded_atoms(*index).any(|neighbor| {
+                                    current
+                                        .atoms
+                                        .read_atom(neighbor)
+                                        .is_some_and(|atom| atom.element != 6 && atom.element != 1)
+                                }))
+                    })
+                    .map(SelectOne::Index)
+                    .collect();
+                current = Self::RemoveAtoms {
+                    select: SelectMany::Indexes(removed),
+                }
+                .filter(current)?;
+            }
+            Self::AlignTo {
+                select,
+                reference,
+                apply,
+            } => {
+                let selected = select
+                    .to_indexes(&current)
+                    .into_iter()
+                    .filter_map(|index| Some((index, current.atoms.read_atom(index)?)))
+                    .filter(|(_, atom)| atom.is_present())
+                    .collect::<Vec<_>>();
+                let pairs = match reference {
+                    AlignReference::Positions(positions) => {
+                        if positions.len() != selected.len() {
+                            Err(LayerStorageError::ReferenceMismatch(
+                                selected.len(),
+                                positions.len(),
+                            ))?
+                        }
+                        selected
+                            .iter()
+                            .zip(positions)
+                            .map(|((_, atom), position)| (atom.position, *position))
+                            .collect::<Vec<_>>()
+                    }
+                    AlignReference::Structure { structure, by_ids } => {
+                        let reference_index = |index: usize| {
+                            if *by_ids {
+                                let ids = current.ids.as_ref()?;
+                                let reference_ids = structure.ids.as_ref()?;
+                                ids.iter()
+                                    .filter(|(_, current_index)| **current_index == index)
+                                    .find_map(|(id, _)| reference_ids.get(id).copied())
+                            } else {
+                                Some(index)
+                            }
+                        };
+                        selected
+                            .iter()
+                            .filter_map(|(index, atom)| {
+                                let reference =
+                                    structure.atoms.read_atom(reference_index(*index)?)?;
+                                Some((atom.position, reference.position))
+                            })
+                            .collect()
+                    }
+                    AlignReference::IdPositions(positions) => {
+                        let ids = current.ids.clone().unwrap_or_default();
+                        selected
+                            .iter()
+                            .filter_map(|(index, atom)| {
+                                let position = ids
+                                    .iter()
+                                    .filter(|(_, current_index)| *current_index == index)
+                                    .find_map(|(id, _)| positions.get(id))?;
+                                Some((atom.position, *position))
+                            })
+                            .collect()
+                    }
+                    AlignReference::Base(_) => Err(LayerStorageError::UnresolvedBase)?,
+                };
+                let (from, to): (Vec<_>, Vec<_>) = pairs.into_iter().unzip();
+                let isometry = kabsch(&from, &to).ok_or(LayerStorageError::ReferenceMismatch(
+                    selected.len(),
+                    to.len(),
+                ))?;
+                current
+                    .atoms
+                    .isometry(isometry, &apply.to_indexes(&current));
+            }
+            Self::Compact => {
+                let order = (0..current.len())
+                    .filter(|index| {
+                        current
+                            .atoms
+                            .read_atom(*index)
+                            .is_some_and(|atom| atom.is_present())
+                    })
+                    .collect::<Vec<_>>();
+                current = current.reindex(&order);
+            }
+            Self::Reorder { order } => {
+                let mut indexes = vec![];
+                let mut placed = BTreeSet::new();
+                let rest = SelectMany::All;
+                for select in order.iter().chain([&rest]) {
+                    for index in select.to_indexes(&current) {
+                        if placed.insert(index) {
+                            indexes.push(index);
+                        }
+                    }
+                }
+                current = current.reindex(&indexes);
+            }
+            Self::RemoveGroups { groups } => {
+                if let Some(current_groups) = &mut current.groups {
+                    for group in groups {
+                        current_groups.remove_left(group);
+                    }
+                }
+            }
+            Self::RenameGroups { groups } => {
+                if let Some(current_groups) = &mut current.groups {
+                    let renamed = groups
+                        .iter()
+                        .map(|(old, new)| {
+                            let indexes = current_groups.get_left(old).copied().collect::<Vec<_>>();
+                            (new.to_string(), indexes)
+                        })
+                        .collect::<Vec<_>>();
+                    for old in groups.keys() {
+                        current_groups.remove_left(old);
+                    }
+                    for (new, indexes) in renamed {
+                        current_groups.insert_left(new, indexes.into_iter());
+                    }
+                }
+            }
+            Self::RemoveIds { ids } => {
+                if let Some(current_ids) = &mut current.ids {
+                    for id in ids {
+                        current_ids.remove(id);
+                    }
+                }
+            }
+            Self::GroupIntersection { name, groups } => {
+                let indexes = groups
+                    .iter()
+                    .map(|group| SelectMany::GroupName(group.to_string()).to_indexes(&current))
+                    .reduce(|a, b| &a & &b)
+                    .unwrap_or_default();
+                let current_groups = current.groups.get_or_insert_with(GroupName::new);
+                current_groups.remove_left(name);
+                current_groups.insert_left(name.to_string(), indexes.into_iter());
+            }
+            Self::GroupDifference {
+                name,
+                group,
+                excludes,
+            } => {
+                let mut indexes = SelectMany::GroupName(group.to_string()).to_indexes(&current);
+                for exclude in excludes {
+                    indexes =
+                        &indexes - &SelectMany::GroupName(exclude.to_string()).to_indexes(&current);
+                }
+                let current_groups = current.groups.get_or_insert_with(GroupName::new);
+                current_groups.remove_left(name);
+                current_groups.insert_left(name.to_string(), indexes.into_iter());
+            }
         }
         Ok(current)
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, PartialOrd, Ord, Eq, Encode, Decode)]
-#[serde(untagged)]
+#[serde(untagged, try_from = "SelectOneLoader")]
 pub enum SelectOne {
     Index(usize),
     IdName(String),
+    /// The atom mapped as `:map` in the first match of a SMARTS-like pattern, or the
+    /// `map`th atom of the pattern if no atom is mapped so, like `{ pattern: "[C:1]=O" }`
+    Pattern {
+        pattern: String,
+        map: usize,
+    },
+}
+
+fn default_map() -> usize {
+    1
+}
+
+/// Check the pattern when loading the workflow, instead of selecting nothing later
+fn deserialize_pattern<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<String, D::Error> {
+    let pattern = String::deserialize(deserializer)?;
+    Pattern::parse(&pattern).map_err(serde::de::Error::custom)?;
+    Ok(pattern)
 }
 
 impl Display for SelectOne {
@@ -461,6 +1137,14 @@ impl SelectOne {
         match self {
             Self::Index(index) => Some(*index),
             Self::IdName(id_name) => layer.ids.as_ref()?.get(id_name).copied(),
+            Self::Pattern { pattern, map } => {
+                let pattern = Pattern::parse(pattern).ok()?;
+                let position = pattern.mapped_atom(*map)?;
+                pattern
+                    .matches(layer)
+                    .first()
+                    .map(|matched| matched[position])
+            }
         }
     }
 
@@ -476,7 +1160,7 @@ impl SelectOne {
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Encode, Decode, Default)]
-#[serde(untagged)]
+#[serde(untagged, try_from = "SelectManyLoader")]
 pub enum SelectMany {
     #[default]
     All,
@@ -485,10 +1169,201 @@ pub enum SelectMany {
         #[serde(default)]
         excludes: Vec<SelectMany>,
     },
+    /// Written as `{ elements: [6] }`, since a bare number is ambiguous with indexes
+    #[serde(serialize_with = "serialize_element")]
     Element(usize),
     Indexes(BTreeSet<SelectOne>),
     Range(RangeInclusive<usize>),
+    /// Written as `=group:"a&b"` for names with characters of selector expressions
+    #[serde(serialize_with = "serialize_group_name")]
     GroupName(String),
+    /// Select atoms hidden or not by `Hide` and `UnHide` layers, like `{ hidden: true }`
+    Visibility {
+        hidden: bool,
+    },
+    /// Select atoms bonded to the given atom directly or indirectly, like `{ fragment: Ru }`
+    Fragment {
+        fragment: SelectOne,
+    },
+    /// Select atoms on the `to` side of the bond between `from` and `to`, including `to`
+    Side {
+        from: SelectOne,
+        to: SelectOne,
+    },
+    /// Select atoms within `radius` Angstrom of the given atom, like `{ center: Ru, radius: 3.0 }`
+    Within {
+        center: SelectOne,
+        radius: f64,
+    },
+    /// Select atoms within `radius` Angstrom of any atom in the group, including the group
+    WithinGroup {
+        group: String,
+        radius: f64,
+    },
+    /// Select atoms at most `depth` bonds away from the given atom, excluding itself,
+    /// like `{ of: Ru }` for the first coordination shell
+    Neighbors {
+        of: SelectOne,
+        #[serde(default = "default_depth")]
+        depth: usize,
+    },
+    /// Select atoms in all of the selections, like `{ intersect: [ligand, { center: Ru, radius: 3.0 }] }`
+    Intersect {
+        intersect: Vec<SelectMany>,
+    },
+    /// Select atoms matching a SMARTS-like pattern in all matches, only the atoms with map
+    /// numbers if any, like `{ pattern: "[C:1]=O" }` for carbonyl carbons
+    Pattern {
+        pattern: String,
+    },
+    /// Select atoms of any of the elements or element classes, like
+    /// `{ elements: [Cl, 35, transition_metal] }`
+    Elements {
+        elements: Vec<ElementClass>,
+    },
+}
+
+fn serialize_element<S: serde::Serializer>(
+    element: &usize,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut map = serializer.serialize_map(Some(1))?;
+    map.serialize_entry("elements", &[ElementClass::Element(*element)])?;
+    map.end()
+}
+
+/// Characters of selector expressions, which group names need the `=group:` form for
+const EXPRESSION_OPERATORS: [char; 4] = ['&', '|', '!', '('];
+
+fn serialize_group_name<S: serde::Serializer>(
+    name: &String,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    if name.contains(EXPRESSION_OPERATORS) {
+        let quote = if name.contains('"') { '\'' } else { '"' };
+        serializer.serialize_str(&format!("=group:{quote}{name}{quote}"))
+    } else {
+        serializer.serialize_str(name)
+    }
+}
+
+fn default_depth() -> usize {
+    1
+}
+
+/// Strings starting with `=` are parsed as selector expressions like `=id:Ru`, and other
+/// strings are ids as before
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SelectOneLoader {
+    Index(usize),
+    Name(String),
+    Pattern {
+        #[serde(deserialize_with = "deserialize_pattern")]
+        pattern: String,
+        #[serde(default = "default_map")]
+        map: usize,
+    },
+}
+
+impl TryFrom<SelectOneLoader> for SelectOne {
+    type Error = anyhow::Error;
+    fn try_from(value: SelectOneLoader) -> Result<Self, Self::Error> {
+        Ok(match value {
+            SelectOneLoader::Index(index) => Self::Index(index),
+            SelectOneLoader::Name(name) => match name.strip_prefix('=') {
+                Some(expression) => parse_select_one(expression)?,
+                None => Self::IdName(name),
+            },
+            SelectOneLoader::Pattern { pattern, map } => Self::Pattern { pattern, map },
+        })
+    }
+}
+
+/// Strings starting with `=` are parsed as selector expressions like `=group:bone & !id:Ru`,
+/// and other strings are group names as before, unless they contain `&`, `|`, `!` or `(`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SelectManyLoader {
+    All,
+    Complex {
+        includes: Vec<SelectMany>,
+        #[serde(default)]
+        excludes: Vec<SelectMany>,
+    },
+    Number(usize),
+    Indexes(BTreeSet<SelectOne>),
+    Range(RangeInclusive<usize>),
+    Name(String),
+    Visibility {
+        hidden: bool,
+    },
+    Fragment {
+        fragment: SelectOne,
+    },
+    Side {
+        from: SelectOne,
+        to: SelectOne,
+    },
+    Within {
+        center: SelectOne,
+        radius: f64,
+    },
+    WithinGroup {
+        group: String,
+        radius: f64,
+    },
+    Neighbors {
+        of: SelectOne,
+        #[serde(default = "default_depth")]
+        depth: usize,
+    },
+    Intersect {
+        intersect: Vec<SelectMany>,
+    },
+    Pattern {
+        #[serde(deserialize_with = "deserialize_pattern")]
+        pattern: String,
+    },
+    Elements {
+        elements: Vec<ElementClass>,
+    },
+}
+
+impl TryFrom<SelectManyLoader> for SelectMany {
+    type Error = anyhow::Error;
+    fn try_from(value: SelectManyLoader) -> Result<Self, Self::Error> {
+        Ok(match value {
+            SelectManyLoader::All => Self::All,
+            SelectManyLoader::Complex { includes, excludes } => {
+                Self::Complex { includes, excludes }
+            }
+            SelectManyLoader::Number(number) => Err(anyhow::anyhow!(
+                "Bare number {number} is ambiguous, use {{ elements: [{number}] }} for an element or [{number}] for an index"
+            ))?,
+            SelectManyLoader::Indexes(indexes) => Self::Indexes(indexes),
+            SelectManyLoader::Range(range) => Self::Range(range),
+            SelectManyLoader::Name(name) => match name.strip_prefix('=') {
+                Some(expression) => parse_select_many(expression)?,
+                None if name.contains(EXPRESSION_OPERATORS) => Err(anyhow::anyhow!(
+                    "Group name {name} looks like a selector expression, write ={name} for the expression or =group:\"{name}\" for the group"
+                ))?,
+                None => Self::GroupName(name),
+            },
+            SelectManyLoader::Visibility { hidden } => Self::Visibility { hidden },
+            SelectManyLoader::Fragment { fragment } => Self::Fragment { fragment },
+            SelectManyLoader::Side { from, to } => Self::Side { from, to },
+            SelectManyLoader::Within { center, radius } => Self::Within { center, radius },
+            SelectManyLoader::WithinGroup { group, radius } => Self::WithinGroup { group, radius },
+            SelectManyLoader::Neighbors { of, depth } => Self::Neighbors { of, depth },
+            SelectManyLoader::Intersect { intersect } => Self::Intersect { intersect },
+            SelectManyLoader::Pattern { pattern } => Self::Pattern { pattern },
+            SelectManyLoader::Elements { elements } => match elements.as_slice() {
+                [ElementClass::Element(element)] => Self::Element(*element),
+                _ => Self::Elements { elements },
+            },
+        })
+    }
 }
 
 impl SelectMany {
@@ -525,10 +1400,124 @@ impl SelectMany {
                 }
                 selected
             }
+            Self::Visibility { hidden } => (0..layer.atoms.len())
+                .filter(|index| {
+                    layer
+                        .atoms
+                        .read_atom(*index)
+                        .map(|atom| atom.is_present() && atom.hidden == *hidden)
+                        .unwrap_or_default()
+                })
+                .collect(),
+            Self::Fragment { fragment } => fragment
+                .to_index(layer)
+                .map(|index| layer.fragment(index))
+                .unwrap_or_default(),
+            Self::Side { from, to } => match (from.to_index(layer), to.to_index(layer)) {
+                (Some(from), Some(to)) => layer.side(from, to),
+                _ => BTreeSet::new(),
+            },
+            Self::Within { center, radius } => center
+                .to_index(layer)
+                .map(|center| layer.atoms_within(&BTreeSet::from([center]), *radius))
+                .unwrap_or_default(),
+            Self::WithinGroup { group, radius } => layer.atoms_within(
+                &Self::GroupName(group.to_string()).to_indexes(layer),
+                *radius,
+            ),
+            Self::Neighbors { of, depth } => {
+                let Some(center) = of.to_index(layer) else {
+                    return BTreeSet::new();
+                };
+                let mut visited = BTreeSet::from([center]);
+                let mut shell = visited.clone();
+                for _ in 0..*depth {
+                    shell = shell
+                        .iter()
+                        .flat_map(|index| layer.bonded_atoms(*index))
+                        .filter(|index| !visited.contains(index))
+                        .collect();
+                    visited.extend(shell.iter().copied());
+                }
+                visited.remove(&center);
+                visited
+            }
+            Self::Intersect { intersect } => intersect
+                .iter()
+                .map(|select| select.to_indexes(layer))
+                .reduce(|a, b| &a & &b)
+                .unwrap_or_default(),
+            Self::Pattern { pattern } => {
+                let Ok(pattern) = Pattern::parse(pattern) else {
+                    return BTreeSet::new();
+                };
+                let selected = pattern.selected_atoms();
+                pattern
+                    .matches(layer)
+                    .into_iter()
+                    .flat_map(|matched| selected.iter().map(move |index| matched[*index]))
+                    .collect()
+            }
+            Self::Elements { elements } => (0..layer.atoms.len())
+                .filter(|index| {
+                    layer
+                        .atoms
+                        .read_atom(*index)
+                        .map(|atom| {
+                            atom.is_present()
+                                && elements.iter().any(|class| class.contains(atom.element))
+                        })
+                        .unwrap_or_default()
+                })
+                .collect(),
         }
     }
 }
 
+fn atom_position(current: &SparseMolecule, index: usize) -> Point3<f64> {
+    current
+        .atoms
+        .read_atom(index)
+        .map(|atom| atom.position)
+        .unwrap_or_default()
+}
+
+/// Atoms to be moved with the `to` atom when changing internal coordinates. It is the
+/// atoms in `select` if given, the `to` side of the bond if `from` and `to` are bonded,
+/// or the fragment of `to` if it is not connected to `from`.
+fn moved_atoms(
+    current: &SparseMolecule,
+    from: usize,
+    to: usize,
+    select: &Option<SelectMany>,
+) -> Result<BTreeSet<usize>, LayerStorageError> {
+    if let Some(select) = select {
+        return Ok(select.to_indexes(current));
+    }
+    let moved = current.side(from, to);
+    if current.bonded_atoms(from).any(|index| index == to) {
+        if moved.intersection(&current.side(to, from)).next().is_some() {
+            Err(LayerStorageError::BondInRing(from, to))?
+        }
+    } else if moved.len() != current.fragment(to).len() {
+        Err(LayerStorageError::BondNotFound(from, to))?
+    }
+    Ok(moved)
+}
+
+/// Total charge and multiplicity after appending a molecule, unpaired electrons of both
+/// are assumed to be parallel. `None` if neither is set explicitly.
+fn combined_charge(
+    current: &SparseMolecule,
+    appended: &SparseMolecule,
+) -> (Option<i64>, Option<usize>) {
+    let charge = (current.charge.is_some() || appended.charge.is_some())
+        .then(|| current.total_charge() + appended.total_charge());
+    let multiplicity = (current.multiplicity.is_some() || appended.multiplicity.is_some())
+        .then(|| current.spin_multiplicity() + appended.spin_multiplicity() - 1);
+    (charge, multiplicity)
+}
+
 impl Value for Layer {
     type AsBytes<'a> = Vec<u8>;
     type SelfType<'a> = Layer;
@@ -562,7 +1551,15 @@ impl Value for Layer {
 pub enum LayerStorageError {
     NoSuchLayer(u64),
     SelectNotFound(SelectOne),
-    HideOverflow { idx: usize, current_value: usize },
+    LatticeNotFound,
+    InvalidMultiplicity(i64),
+    BondNotFound(usize, usize),
+    BondInRing(usize, usize),
+    /// Numbers of the selected atoms and the matched reference positions for alignment
+    ReferenceMismatch(usize, usize),
+    /// The `base` reference of `AlignTo` is only resolved by `AppendLayers` and
+    /// `DistributeLayers` runners
+    UnresolvedBase,
 }
 
 impl From<SelectOne> for LayerStorageError {
@@ -571,15 +1568,6 @@ impl From<SelectOne> for LayerStorageError {
     }
 }
 
-impl From<(usize, usize)> for LayerStorageError {
-    fn from(value: (usize, usize)) -> Self {
-        Self::HideOverflow {
-            idx: value.0,
-            current_value: value.1,
-        }
-    }
-}
-
 impl std::fmt::Display for LayerStorageError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{:#?}", self)
@@ -587,3 +1575,629 @@ impl std::fmt::Display for LayerStorageError {
 }
 
 impl std::error::Error for LayerStorageError {}
+
+#[test]
+fn hide_and_unhide_atoms() {
+    let molecule = SparseMolecule {
+        atoms: SparseAtomList::from(vec![
+            Atom3D {
+                element: 8,
+                ..Default::default()
+            },
+            Atom3D {
+                element: 1,
+                ..Default::default()
+            },
+            Atom3D {
+                element: 1,
+                ..Default::default()
+            },
+        ]),
+        ..Default::default()
+    };
+    let hide = Layer::Hide {
+        select: SelectMany::Element(1),
+    };
+    let hidden = hide.filter(hide.filter(molecule).unwrap()).unwrap();
+    let hidden_select = SelectMany::Visibility { hidden: true };
+    assert_eq!(hidden_select.to_indexes(&hidden), BTreeSet::from([1, 2]));
+    let atoms: Vec<Atom3D> = hidden.atoms.clone().into();
+    assert_eq!(atoms.len(), 1);
+    assert_eq!(hidden.atoms.to_continuous_atoms(true).len(), 3);
+    let shown = Layer::UnHide {
+        select: SelectMany::All,
+    }
+    .filter(hidden)
+    .unwrap();
+    assert!(hidden_select.to_indexes(&shown).is_empty());
+}
+
+#[test]
+fn wrap_atoms_into_lattice() {
+    let molecule = SparseMolecule {
+        atoms: SparseAtomList::from(vec![Atom3D {
+            element: 6,
+            position: Point3::new(-1., 11., 4.),
+            ..Default::default()
+        }]),
+        ..Default::default()
+    };
+    let wrap = Layer::Wrap {
+        select: SelectMany::All,
+    };
+    assert!(wrap.filter(molecule.clone()).is_err());
+    let periodic = Layer::SetLattice {
+        lattice: Some(Lattice::orthorhombic(10., 10., 10.)),
+    }
+    .filter(molecule)
+    .unwrap();
+    let wrapped = wrap.filter(periodic).unwrap();
+    let position = wrapped.atoms.read_atom(0).unwrap().position;
+    assert!((position - Point3::new(9., 1., 4.)).norm() < 1e-10);
+}
+
+#[test]
+fn select_fragment_and_side() {
+    let mut molecule = SparseMolecule {
+        atoms: SparseAtomList::from(vec![
+            Atom3D {
+                element: 6,
+                ..Default::default()
+            };
+            6
+        ]),
+        ..Default::default()
+    };
+    // Chain 0-1-2-3 with 3 removed from the bond 2-3, and a separated pair 4-5
+    for (a, b, bond) in [(0, 1, 1.), (1, 2, 1.), (2, 3, 0.), (4, 5, 1.)] {
+        molecule.bonds.set_bond(a, b, Some(bond));
+    }
+    let fragment = SelectMany::Fragment {
+        fragment: SelectOne::Index(1),
+    };
+    assert_eq!(fragment.to_indexes(&molecule), BTreeSet::from([0, 1, 2]));
+    let side = SelectMany::Side {
+        from: SelectOne::Index(0),
+        to: SelectOne::Index(1),
+    };
+    assert_eq!(side.to_indexes(&molecule), BTreeSet::from([1, 2]));
+    assert_eq!(
+        molecule.connected_components(),
+        vec![
+            BTreeSet::from([0, 1, 2]),
+            BTreeSet::from([3]),
+            BTreeSet::from([4, 5])
+        ]
+    );
+    let parsed: SelectMany = serde_yaml::from_str("{ from: 0, to: 1 }").unwrap();
+    assert_eq!(parsed, side);
+}
+
+#[test]
+fn charge_and_multiplicity() {
+    let water = SparseMolecule {
+        atoms: SparseAtomList::from(vec![
+            Atom3D {
+                element: 8,
+                ..Default::default()
+            },
+            Atom3D {
+                element: 1,
+                ..Default::default()
+            },
+            Atom3D {
+                element: 1,
+                ..Default::default()
+            },
+        ]),
+        ..Default::default()
+    };
+    assert_eq!((water.total_charge(), water.spin_multiplicity()), (0, 1));
+    let hydronium = Layer::AppendAtoms {
+        atoms: vec![Atom3D {
+            element: 1,
+            ..Default::default()
+        }],
+    }
+    .filter(water.clone())
+    .unwrap();
+    let hydronium = Layer::SetCharge {
+        charge: Some(1),
+        multiplicity: None,
+    }
+    .filter(hydronium)
+    .unwrap();
+    assert_eq!(hydronium.spin_multiplicity(), 1);
+    let cluster = Layer::Append {
+        name: "H3O".to_string(),
+        data: hydronium,
+    }
+    .filter(water)
+    .unwrap();
+    assert_eq!(cluster.charge, Some(1));
+    let radical = Layer::AdjustCharge {
+        charge: -1,
+        multiplicity: 1,
+    }
+    .filter(cluster)
+    .unwrap();
+    assert_eq!(
+        (radical.total_charge(), radical.spin_multiplicity()),
+        (0, 2)
+    );
+}
+
+#[test]
+fn set_dihedral_of_butane() {
+    let positions = [
+        (6, -1.5, 1., 0.),
+        (6, -0.75, 0., 0.),
+        (6, 0.75, 0., 0.),
+        (6, 1.5, 1., 0.),
+        (1, 2.5, 0.8, 0.),
+    ];
+    let mut molecule = SparseMolecule {
+        atoms: SparseAtomList::from(
+            positions
+                .map(|(element, x, y, z)| Atom3D {
+                    element,
+                    position: Point3::new(x, y, z),
+                    ..Default::default()
+                })
+                .to_vec(),
+        ),
+        ..Default::default()
+    };
+    for (a, b) in [(0, 1), (1, 2), (2, 3), (3, 4)] {
+        molecule.bonds.set_bond(a, b, Some(1.));
+    }
+    let layer = Layer::SetDihedral {
+        a: SelectOne::Index(0),
+        b: SelectOne::Index(1),
+        c: SelectOne::Index(2),
+        d: SelectOne::Index(3),
+        value: 60.,
+        degree: true,
+    };
+    let rotated = layer.filter(molecule.clone()).unwrap();
+    let position = |index: usize| rotated.atoms.read_atom(index).unwrap().position;
+    let value = dihedral(position(0), position(1), position(2), position(3));
+    assert!((value - PI / 3.).abs() < 1e-10);
+    assert_eq!(position(0), Point3::new(-1.5, 1., 0.));
+    assert!(((position(4) - position(3)).norm() - (2.5f64 - 1.5).hypot(0.2)).abs() < 1e-10);
+    molecule.bonds.set_bond(0, 3, Some(1.));
+    assert!(layer.filter(molecule).is_err());
+}
+
+#[test]
+fn set_distance_and_angle() {
+    let positions = [
+        (8, 0., 0., 0.),
+        (1, 0.96, 0., 0.),
+        (1, -0.24, 0.93, 0.),
+        (18, 5., 0., 0.),
+    ];
+    let mut molecule = SparseMolecule {
+        atoms: SparseAtomList::from(
+            positions
+                .map(|(element, x, y, z)| Atom3D {
+                    element,
+                    position: Point3::new(x, y, z),
+                    ..Default::default()
+                })
+                .to_vec(),
+        ),
+        ..Default::default()
+    };
+    molecule.bonds.set_bond(0, 1, Some(1.));
+    molecule.bonds.set_bond(0, 2, Some(1.));
+    let molecule = Layer::SetAngle {
+        a: SelectOne::Index(1),
+        b: SelectOne::Index(0),
+        c: SelectOne::Index(2),
+        value: 120.,
+        degree: true,
+        select: None,
+    }
+    .filter(molecule)
+    .unwrap();
+    let molecule = Layer::SetDistance {
+        a: SelectOne::Index(0),
+        b: SelectOne::Index(3),
+        value: 3.,
+        select: None,
+    }
+    .filter(molecule)
+    .unwrap();
+    let position = |index: usize| molecule.atoms.read_atom(index).unwrap().position;
+    let value = angle(position(1), position(0), position(2));
+    assert!((value - PI * 2. / 3.).abs() < 1e-10);
+    assert!(((position(2) - position(0)).norm() - 0.24f64.hypot(0.93)).abs() < 1e-10);
+    assert!(((position(3) - position(0)).norm() - 3.).abs() < 1e-10);
+    assert_eq!(position(1), Point3::new(0.96, 0., 0.));
+}
+
+#[test]
+fn substitute_hydrogen() {
+    let molecule = |atoms: &[(usize, f64, f64, f64)]| SparseMolecule {
+        atoms: SparseAtomList::from(
+            atoms
+                .iter()
+                .map(|(element, x, y, z)| Atom3D {
+                    element: *element,
+                    position: Point3::new(*x, *y, *z),
+                    ..Default::default()
+                })
+                .collect::<Vec<_>>(),
+        ),
+        ..Default::default()
+    };
+    let mut water = molecule(&[(8, 0., 0., 0.), (1, 0., -0.96, 0.), (1, 0.93, 0.24, 0.)]);
+    water.bonds.set_bond(0, 1, Some(1.));
+    water.bonds.set_bond(0, 2, Some(1.));
+    let mut methyl = molecule(&[(1, 0., 0., 0.), (6, 1.09, 0., 0.), (1, 1.45, 1.03, 0.)]);
+    methyl.bonds.set_bond(0, 1, Some(1.));
+    methyl.bonds.set_bond(1, 2, Some(1.));
+    methyl.ids = Some(BTreeMap::from([("C".to_string(), 1)]));
+    let result = Layer::Substitute {
+        center: SelectOne::Index(0),
+        replace: SelectOne::Index(1),
+        name: "Me".to_string(),
+        data: methyl,
+    }
+    .filter(water.clone())
+    .unwrap();
+    let carbon = result.atoms.read_atom(1).unwrap();
+    assert_eq!(carbon.element, 6);
+    assert!((carbon.position - Point3::new(0., -1.42, 0.)).norm() < 1e-10);
+    let hydrogen = result.atoms.read_atom(3).unwrap().position;
+    assert!(((hydrogen - carbon.position).norm() - 0.36f64.hypot(1.03)).abs() < 1e-10);
+    assert_eq!(result.bonds.read_bond(1, 3), Some(1.));
+    assert_eq!(result.bonds.read_bond(0, 1), Some(1.));
+    assert_eq!(result.atoms.read_atom(2), water.atoms.read_atom(2));
+    assert_eq!(result.ids.unwrap()["Me_C"], 1);
+    let group = result.groups.unwrap();
+    assert_eq!(
+        group.get_left(&"Me".to_string()).collect::<Vec<_>>(),
+        [&1, &3]
+    );
+}
+
+#[test]
+fn add_and_remove_hydrogens() {
+    let mut methanol = SparseMolecule {
+        atoms: SparseAtomList::from(vec![
+            Atom3D {
+                element: 6,
+                ..Default::default()
+            },
+            Atom3D {
+                element: 8,
+                position: Point3::new(1.43, 0., 0.),
+                ..Default::default()
+            },
+        ]),
+        ..Default::default()
+    };
+    methanol.bonds.set_bond(0, 1, Some(1.));
+    let methanol = Layer::AddHydrogens {
+        select: SelectMany::All,
+        name: Some("H".to_string()),
+    }
+    .filter(methanol)
+    .unwrap();
+    assert_eq!(methanol.len(), 6);
+    let ids = methanol.ids.clone().unwrap();
+    assert_eq!(
+        ids.keys().collect::<Vec<_>>(),
+        ["H_0_1", "H_0_2", "H_0_3", "H_1_1"]
+    );
+    let hydroxyl = methanol.atoms.read_atom(ids["H_1_1"]).unwrap().position;
+    assert!(
+        (angle(Point3::origin(), Point3::new(1.43, 0., 0.), hydroxyl) - (-1f64 / 3.).acos()).abs()
+            < 1e-10
+    );
+    assert_eq!(methanol.bonds.read_bond(1, ids["H_1_1"]), Some(1.));
+    let stripped = Layer::RemoveHydrogens {
+        select: SelectMany::All,
+        keep_polar: true,
+    }
+    .filter(methanol)
+    .unwrap();
+    let present = (0..stripped.len())
+        .filter(|index| {
+            stripped
+                .atoms
+                .read_atom(*index)
+                .is_some_and(|atom| atom.is_present())
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(present, [0, 1, ids["H_1_1"]]);
+}
+
+#[test]
+fn align_to_base_ids() {
+    let positions = [
+        (6, 0., 0., 0.),
+        (8, 1.2, 0., 0.),
+        (1, -0.5, 0.9, 0.),
+        (1, -0.5, -0.9, 0.3),
+    ];
+    let mut base = SparseMolecule {
+        atoms: SparseAtomList::from(
+            positions
+                .map(|(element, x, y, z)| Atom3D {
+                    element,
+                    position: Point3::new(x, y, z),
+                    ..Default::default()
+                })
+                .to_vec(),
+        ),
+        ..Default::default()
+    };
+    base.ids = Some(BTreeMap::from([
+        ("C".to_string(), 0),
+        ("O".to_string(), 1),
+        ("H1".to_string(), 2),
+        ("H2".to_string(), 3),
+    ]));
+    let moved = Layer::Rotation {
+        select: SelectMany::All,
+        center: Point3::new(1., 2., 3.),
+        axis: Vector3::new(1., 1., 0.),
+        angle: 70.,
+        degree: true,
+    }
+    .filter(base.clone())
+    .unwrap();
+    let layer: Layer =
+        serde_yaml::from_str("type: AlignTo\nselect: null\nreference: base").unwrap();
+    assert!(matches!(
+        layer.filter(moved.clone()),
+        Err(LayerStorageError::UnresolvedBase)
+    ));
+    let resolved = layer.with_base(&base);
+    let Layer::AlignTo {
+        reference: AlignReference::IdPositions(positions),
+        ..
+    } = &resolved
+    else {
+        panic!("Base reference is not resolved to positions");
+    };
+    assert_eq!(positions.len(), 4);
+    let aligned = resolved.filter(moved).unwrap();
+    for index in 0..base.len() {
+        let (a, b) = (
+            aligned.atoms.read_atom(index).unwrap(),
+            base.atoms.read_atom(index).unwrap(),
+        );
+        assert!((a.position - b.position).norm() < 1e-8);
+    }
+}
+
+#[test]
+fn compact_and_reorder() {
+    let mut molecule = SparseMolecule {
+        atoms: SparseAtomList::from(
+            [6, 1, 8, 1]
+                .map(|element| Atom3D {
+                    element,
+                    ..Default::default()
+                })
+                .to_vec(),
+        ),
+        ..Default::default()
+    };
+    molecule.bonds.set_bond(0, 2, Some(2.));
+    molecule.bonds.set_bond(2, 3, Some(1.));
+    molecule.ids = Some(BTreeMap::from([
+        ("H1".to_string(), 1),
+        ("O".to_string(), 2),
+    ]));
+    molecule.groups = Some(GroupName::from_iter([
+        ("OH".to_string(), 2),
+        ("OH".to_string(), 3),
+    ]));
+    let molecule = Layer::RemoveAtoms {
+        select: SelectMany::Indexes(BTreeSet::from([SelectOne::Index(1)])),
+    }
+    .filter(molecule)
+    .unwrap();
+    let molecule = Layer::Compact.filter(molecule).unwrap();
+    assert_eq!(molecule.len(), 3);
+    assert_eq!(
+        molecule.ids.clone().unwrap(),
+        BTreeMap::from([("O".to_string(), 1)])
+    );
+    assert_eq!(molecule.bonds.read_bond(0, 1), Some(2.));
+    let molecule = Layer::Reorder {
+        order: vec![SelectMany::GroupName("OH".to_string())],
+    }
+    .filter(molecule)
+    .unwrap();
+    let elements = (0..3)
+        .map(|index| molecule.atoms.read_atom(index).unwrap().element)
+        .collect::<Vec<_>>();
+    assert_eq!(elements, [8, 1, 6]);
+    assert_eq!(molecule.bonds.read_bond(0, 2), Some(2.));
+    assert_eq!(molecule.bonds.read_bond(0, 1), Some(1.));
+    assert_eq!(molecule.ids.unwrap()["O"], 0);
+    let group = molecule.groups.unwrap();
+    assert_eq!(
+        group.get_left(&"OH".to_string()).collect::<Vec<_>>(),
+        [&0, &1]
+    );
+}
+
+#[test]
+fn manage_groups_and_ids() {
+    let mut molecule = SparseMolecule {
+        atoms: SparseAtomList::from(vec![Atom3D::default(); 4]),
+        ..Default::default()
+    };
+    molecule.ids = Some(BTreeMap::from([("A".to_string(), 0), ("B".to_string(), 1)]));
+    molecule.groups = Some(GroupName::from_iter([
+        ("water1".to_string(), 0),
+        ("water1".to_string(), 1),
+        ("water2".to_string(), 1),
+        ("water2".to_string(), 2),
+        ("solvent".to_string(), 3),
+    ]));
+    let layers = [
+        Layer::GroupIntersection {
+            name: "shared".to_string(),
+            groups: vec!["water1".to_string(), "water2".to_string()],
+        },
+        Layer::GroupDifference {
+            name: "water1".to_string(),
+            group: "water1".to_string(),
+            excludes: vec!["water2".to_string()],
+        },
+        Layer::RenameGroups {
+            groups: BTreeMap::from([("water2".to_string(), "solvent".to_string())]),
+        },
+        Layer::RemoveIds {
+            ids: vec!["A".to_string()],
+        },
+        Layer::RemoveGroups {
+            groups: vec!["water1".to_string()],
+        },
+    ];
+    let molecule = layers
+        .iter()
+        .fold(molecule, |molecule, layer| layer.filter(molecule).unwrap());
+    let select = |name: &str| SelectMany::GroupName(name.to_string()).to_indexes(&molecule);
+    assert_eq!(select("shared"), BTreeSet::from([1]));
+    assert_eq!(select("solvent"), BTreeSet::from([1, 2, 3]));
+    assert!(select("water1").is_empty() && select("water2").is_empty());
+    assert_eq!(
+        molecule.ids.clone().unwrap(),
+        BTreeMap::from([("B".to_string(), 1)])
+    );
+}
+
+#[test]
+fn distance_and_graph_selectors() {
+    let mut chain = SparseMolecule {
+        atoms: SparseAtomList::from(
+            (0..5)
+                .map(|index| Atom3D {
+                    element: 6,
+                    position: Point3::new(index as f64 * 1.5, 0., 0.),
+                    ..Default::default()
+                })
+                .collect::<Vec<_>>(),
+        ),
+        ..Default::default()
+    };
+    for index in 0..4 {
+        chain.bonds.set_bond(index, index + 1, Some(1.));
+    }
+    chain.ids = Some(BTreeMap::from([("C2".to_string(), 2)]));
+    chain.groups = Some(GroupName::from_iter([("end".to_string(), 4)]));
+    let select_in = |yaml: &str, molecule: &SparseMolecule| {
+        serde_yaml::from_str::<SelectMany>(yaml)
+            .unwrap()
+            .to_indexes(molecule)
+    };
+    let select = |yaml: &str| select_in(yaml, &chain);
+    assert_eq!(
+        select("{ center: C2, radius: 1.6 }"),
+        BTreeSet::from([1, 2, 3])
+    );
+    assert_eq!(
+        select("{ group: end, radius: 3.0 }"),
+        BTreeSet::from([2, 3, 4])
+    );
+    assert_eq!(select("{ of: C2 }"), BTreeSet::from([1, 3]));
+    assert_eq!(select("{ of: 0, depth: 2 }"), BTreeSet::from([1, 2]));
+    assert_eq!(
+        select("{ intersect: [{ of: C2, depth: 2 }, { group: end, radius: 3.0 }] }"),
+        BTreeSet::from([3, 4])
+    );
+    let mut periodic = chain.clone();
+    periodic.lattice = Some(Lattice::orthorhombic(7.5, 10., 10.));
+    assert_eq!(
+        select_in("{ center: 0, radius: 1.6 }", &periodic),
+        BTreeSet::from([0, 1, 4])
+    );
+}
+
+#[test]
+fn select_by_pattern() {
+    let mut molecule = SparseMolecule {
+        atoms: SparseAtomList::from(
+            [6, 8, 6, 8, 7]
+                .map(|element| Atom3D {
+                    element,
+                    ..Default::default()
+                })
+                .to_vec(),
+        ),
+        ..Default::default()
+    };
+    for (a, b, bond) in [(0, 1, 2.), (0, 2, 1.), (2, 3, 2.), (2, 4, 1.)] {
+        molecule.bonds.set_bond(a, b, Some(bond));
+    }
+    let select: SelectMany = serde_yaml::from_str(r#"{ pattern: "[C:1]=O" }"#).unwrap();
+    assert_eq!(select.to_indexes(&molecule), BTreeSet::from([0, 2]));
+    let amide: SelectOne = serde_yaml::from_str(r#"{ pattern: "NC=O", map: 2 }"#).unwrap();
+    assert_eq!(amide.to_index(&molecule), Some(2));
+    assert!(serde_yaml::from_str::<SelectMany>(r#"{ pattern: "C(=O" }"#).is_err());
+}
+
+#[test]
+fn load_names_and_expressions() {
+    let residue = SelectMany::GroupName("A:HIS57".to_string());
+    let yaml = serde_yaml::to_string(&residue).unwrap();
+    assert_eq!(serde_yaml::from_str::<SelectMany>(&yaml).unwrap(), residue);
+    let atom = SelectOne::IdName("A:HIS57:CA".to_string());
+    let yaml = serde_yaml::to_string(&atom).unwrap();
+    assert_eq!(serde_yaml::from_str::<SelectOne>(&yaml).unwrap(), atom);
+    for name in ["all", "hidden", "*"] {
+        assert_eq!(
+            serde_yaml::from_str::<SelectMany>(&format!("'{name}'")).unwrap(),
+            SelectMany::GroupName(name.to_string())
+        );
+    }
+    assert_eq!(
+        serde_yaml::from_str::<SelectMany>("'=group:\"A:HIS57\" | hidden'").unwrap(),
+        SelectMany::Complex {
+            includes: vec![residue, SelectMany::Visibility { hidden: true }],
+            excludes: vec![],
+        }
+    );
+    assert_eq!(
+        serde_yaml::from_str::<SelectOne>("'=index:3'").unwrap(),
+        SelectOne::Index(3)
+    );
+    assert!(serde_yaml::from_str::<SelectMany>("'=group:'").is_err());
+    for expression in [
+        "group:bone & element:C & !id:Ru",
+        "within(Ru, 3.0) | 12..20",
+    ] {
+        assert!(serde_yaml::from_str::<SelectMany>(&format!("'{expression}'")).is_err());
+        assert!(serde_yaml::from_str::<SelectMany>(&format!("'={expression}'")).is_ok());
+    }
+    for name in ["a&b", "ligand(1)", "\"!\""] {
+        let group = SelectMany::GroupName(name.to_string());
+        let json = serde_json::to_string(&group).unwrap();
+        assert_eq!(serde_json::from_str::<SelectMany>(&json).unwrap(), group);
+    }
+}
+
+#[test]
+fn load_explicit_elements() {
+    let carbon = SelectMany::Element(6);
+    let yaml = serde_yaml::to_string(&carbon).unwrap();
+    assert_eq!(serde_yaml::from_str::<SelectMany>(&yaml).unwrap(), carbon);
+    assert_eq!(
+        serde_yaml::from_str::<SelectMany>("{ elements: [C] }").unwrap(),
+        carbon
+    );
+    assert!(serde_yaml::from_str::<SelectMany>("6").is_err());
+    assert_eq!(
+        serde_yaml::from_str::<SelectMany>("[6]").unwrap(),
+        SelectMany::Indexes(BTreeSet::from([SelectOne::Index(6)]))
+    );
+}
@@ -2,14 +2,22 @@
 
 /// Chemistry concept data structure and functions
 pub mod chemistry;
+/// Molecular descriptors like formula, mass and inertia
+pub mod descriptors;
+/// Built-in element property tables
+pub mod elements;
 /// Functions for calling external programs like openbabel and sed
 pub mod external;
 /// Atom group management
 pub mod group_name;
 /// Input/Output utils
 pub mod io;
+/// Unit cells of periodic structures
+pub mod lattice;
 /// Layers for storage molecular modeling process
 pub mod layer;
+/// Textual selector expressions
+pub mod selector;
 /// Basic data structure for LME molecule
 pub mod sparse_molecule;
 /// Some simple functions used internally
@@ -6,6 +6,7 @@ use std::{
 
 use anyhow::Context;
 use rayon::prelude::*;
+use lmers::elements::override_elements;
 use lmers::workflow::{
     input_data::WorkflowInput,
     runner::{cached_read_stack, RunnerOutput},
@@ -79,6 +80,7 @@ fn main() {
         .collect::<Vec<_>>();
 
     set_path(input.binaries).unwrap();
+    override_elements(&input.elements).unwrap();
 
     let (mut current_window, steps) = if let Some(checkpoint) = &args.checkpoint {
         let num_of_steps = input.steps.0.len();
@@ -0,0 +1,513 @@
+use std::collections::BTreeSet;
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    elements::ElementClass,
+    layer::{SelectMany, SelectOne},
+    utils::smarts::Pattern,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+    Comma,
+    Colon,
+    Range,
+    Word(String),
+    Quoted(String),
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Self::And => "`&`".to_string(),
+            Self::Or => "`|`".to_string(),
+            Self::Not => "`!`".to_string(),
+            Self::Open => "`(`".to_string(),
+            Self::Close => "`)`".to_string(),
+            Self::Comma => "`,`".to_string(),
+            Self::Colon => "`:`".to_string(),
+            Self::Range => "`..`".to_string(),
+            Self::Word(word) => format!("`{word}`"),
+            Self::Quoted(text) => format!("\"{text}\""),
+        }
+    }
+}
+
+/// Parser of the textual selector expressions, like `group:bone & element:C & !id:Ru` or
+/// `within(Ru, 3.0) | 12..20`
+struct Parser<'a> {
+    source: &'a str,
+    /// Tokens and their columns in the source, counted in characters
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(source: &'a str) -> Result<Self> {
+        let chars = source.chars().collect::<Vec<_>>();
+        let mut tokens = vec![];
+        let mut index = 0;
+        let error = |column: usize, message: &str| Self::error_at(source, column, message);
+        while index < chars.len() {
+            let c = chars[index];
+            let start = index;
+            let token = match c {
+                c if c.is_whitespace() => {
+                    index += 1;
+                    continue;
+                }
+                '&' => Token::And,
+                '|' => Token::Or,
+                '!' => Token::Not,
+                '(' => Token::Open,
+                ')' => Token::Close,
+                ',' => Token::Comma,
+                ':' => Token::Colon,
+                '.' if chars.get(index + 1) == Some(&'.') => {
+                    index += 1;
+                    Token::Range
+                }
+                '"' | '\'' => {
+                    let end = chars[index + 1..]
+                        .iter()
+                        .position(|quote| *quote == c)
+                        .ok_or_else(|| error(start, "Unclosed quotation"))?;
+                    let text = chars[index + 1..index + 1 + end].iter().collect();
+                    index += end + 1;
+                    Token::Quoted(text)
+                }
+                _ => {
+                    while index + 1 < chars.len() {
+                        let next = chars[index + 1];
+                        if next.is_whitespace()
+                            || "&|!(),:\"'".contains(next)
+                            || (next == '.' && chars.get(index + 2) == Some(&'.'))
+                        {
+                            break;
+                        }
+                        index += 1;
+                    }
+                    Token::Word(chars[start..=index].iter().collect())
+                }
+            };
+            tokens.push((token, start));
+            index += 1;
+        }
+        Ok(Self {
+            source,
+            tokens,
+            position: 0,
+        })
+    }
+
+    fn error_at(source: &str, column: usize, message: &str) -> anyhow::Error {
+        anyhow!(
+            "{} at column {} of selector\n  {}\n  {}^",
+            message,
+            column + 1,
+            source,
+            " ".repeat(column)
+        )
+    }
+
+    /// Error pointing at the current token, or the end of the source
+    fn error(&self, message: &str) -> anyhow::Error {
+        match self.tokens.get(self.position) {
+            Some((token, column)) => Self::error_at(
+                self.source,
+                *column,
+                &format!("{}, found {}", message, token.describe()),
+            ),
+            None => Self::error_at(
+                self.source,
+                self.source.chars().count(),
+                &format!("{}, found the end", message),
+            ),
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<()> {
+        if self.peek() == Some(&expected) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected {}", expected.describe())))
+        }
+    }
+
+    fn finish(&self) -> Result<()> {
+        if self.position < self.tokens.len() {
+            Err(self.error("Unexpected token"))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// A name of group or id, quoted to include special characters
+    fn name(&mut self) -> Result<String> {
+        match self.peek() {
+            Some(Token::Word(word) | Token::Quoted(word)) => {
+                let word = word.to_string();
+                self.position += 1;
+                Ok(word)
+            }
+            _ => Err(self.error("Expected a name")),
+        }
+    }
+
+    fn number<T: std::str::FromStr>(&mut self, expected: &str) -> Result<T> {
+        match self.peek() {
+            Some(Token::Word(word)) => {
+                let number = word.parse().map_err(|_| self.error(expected))?;
+                self.position += 1;
+                Ok(number)
+            }
+            _ => Err(self.error(expected)),
+        }
+    }
+
+    fn or(&mut self) -> Result<SelectMany> {
+        let mut includes = vec![self.and()?];
+        while self.peek() == Some(&Token::Or) {
+            self.position += 1;
+            includes.push(self.and()?);
+        }
+        Ok(if includes.len() == 1 {
+            includes.remove(0)
+        } else {
+            SelectMany::Complex {
+                includes,
+                excludes: vec![],
+            }
+        })
+    }
+
+    /// Negated terms of the conjunction are excluded from the intersection of the others
+    fn and(&mut self) -> Result<SelectMany> {
+        let mut intersect = vec![];
+        let mut excludes = vec![];
+        loop {
+            if self.peek() == Some(&Token::Not) {
+                self.position += 1;
+                excludes.push(self.primary()?);
+            } else {
+                intersect.push(self.primary()?);
+            }
+            if self.peek() != Some(&Token::And) {
+                break;
+            }
+            self.position += 1;
+        }
+        let included = match intersect.len() {
+            0 => SelectMany::All,
+            1 => intersect.remove(0),
+            _ => SelectMany::Intersect { intersect },
+        };
+        Ok(if excludes.is_empty() {
+            included
+        } else {
+            SelectMany::Complex {
+                includes: vec![included],
+                excludes,
+            }
+        })
+    }
+
+    fn primary(&mut self) -> Result<SelectMany> {
+        let (token, _) = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or_else(|| self.error("Expected a selector"))?;
+        match token {
+            Token::Open => {
+                self.position += 1;
+                let select = self.or()?;
+                self.expect(Token::Close)?;
+                Ok(select)
+            }
+            Token::Not => {
+                self.position += 1;
+                Ok(SelectMany::Complex {
+                    includes: vec![SelectMany::All],
+                    excludes: vec![self.primary()?],
+                })
+            }
+            Token::Quoted(name) => {
+                self.position += 1;
+                Ok(SelectMany::GroupName(name))
+            }
+            Token::Word(word) => {
+                let keyword_position = self.position;
+                self.position += 1;
+                match self.peek() {
+                    Some(Token::Colon) => {
+                        self.position += 1;
+                        self.prefixed(&word, keyword_position)
+                    }
+                    Some(Token::Open) => {
+                        self.position += 1;
+                        let select = self.function(&word, keyword_position)?;
+                        self.expect(Token::Close)?;
+                        Ok(select)
+                    }
+                    Some(Token::Range) => {
+                        self.position -= 1;
+                        let start = self.number("Expected the start index of range")?;
+                        self.position += 1;
+                        let end = self.number("Expected the end index of range")?;
+                        Ok(SelectMany::Range(start..=end))
+                    }
+                    _ => Ok(match word.as_str() {
+                        "all" | "*" => SelectMany::All,
+                        "hidden" => SelectMany::Visibility { hidden: true },
+                        "visible" => SelectMany::Visibility { hidden: false },
+                        _ => match word.parse() {
+                            Ok(index) => {
+                                SelectMany::Indexes(BTreeSet::from([SelectOne::Index(index)]))
+                            }
+                            Err(_) => SelectMany::GroupName(word),
+                        },
+                    }),
+                }
+            }
+            _ => Err(self.error("Expected a selector")),
+        }
+    }
+
+    /// Selectors like `group:bone`, `element:C` and `id:Ru`
+    fn prefixed(&mut self, prefix: &str, keyword_position: usize) -> Result<SelectMany> {
+        Ok(match prefix {
+            "group" => SelectMany::GroupName(self.name()?),
+            "element" => {
+                let mut elements = vec![];
+                if self.peek() == Some(&Token::Open) {
+                    self.position += 1;
+                    loop {
+                        elements.push(self.element_class()?);
+                        if self.peek() != Some(&Token::Comma) {
+                            break;
+                        }
+                        self.position += 1;
+                    }
+                    self.expect(Token::Close)?;
+                } else {
+                    elements.push(self.element_class()?);
+                }
+                match elements.as_slice() {
+                    [ElementClass::Element(element)] => SelectMany::Element(*element),
+                    _ => SelectMany::Elements { elements },
+                }
+            }
+            "id" | "index" => {
+                self.position = keyword_position;
+                SelectMany::Indexes(BTreeSet::from([self.select_one()?]))
+            }
+            _ => {
+                self.position = keyword_position;
+                Err(self.error("Unknown selector prefix"))?
+            }
+        })
+    }
+
+    /// Element symbol, atomic number or element class name like `halogen`
+    fn element_class(&mut self) -> Result<ElementClass> {
+        let class = match self.peek() {
+            Some(Token::Word(word)) => word.parse().ok(),
+            _ => None,
+        }
+        .ok_or_else(|| self.error("Expected an element symbol, number or class"))?;
+        self.position += 1;
+        Ok(class)
+    }
+
+    /// Selectors like `within(Ru, 3.0)`, the closing parenthesis is left to the caller
+    fn function(&mut self, name: &str, keyword_position: usize) -> Result<SelectMany> {
+        Ok(match name {
+            "within" => {
+                let center = self.select_one()?;
+                self.expect(Token::Comma)?;
+                let radius = self.number("Expected a radius")?;
+                SelectMany::Within { center, radius }
+            }
+            "within_group" => {
+                let group = self.name()?;
+                self.expect(Token::Comma)?;
+                let radius = self.number("Expected a radius")?;
+                SelectMany::WithinGroup { group, radius }
+            }
+            "neighbors" => {
+                let of = self.select_one()?;
+                let depth = if self.peek() == Some(&Token::Comma) {
+                    self.position += 1;
+                    self.number("Expected a depth")?
+                } else {
+                    1
+                };
+                SelectMany::Neighbors { of, depth }
+            }
+            "fragment" => SelectMany::Fragment {
+                fragment: self.select_one()?,
+            },
+            "side" => {
+                let from = self.select_one()?;
+                self.expect(Token::Comma)?;
+                let to = self.select_one()?;
+                SelectMany::Side { from, to }
+            }
+            "pattern" => SelectMany::Pattern {
+                pattern: self.pattern()?,
+            },
+            _ => {
+                self.position = keyword_position;
+                Err(self.error("Unknown selector function"))?
+            }
+        })
+    }
+
+    fn pattern(&mut self) -> Result<String> {
+        match self.peek() {
+            Some(Token::Quoted(pattern)) => {
+                let pattern = pattern.to_string();
+                Pattern::parse(&pattern).map_err(|err| self.error(&err.to_string()))?;
+                self.position += 1;
+                Ok(pattern)
+            }
+            _ => Err(self.error("Expected a quoted pattern")),
+        }
+    }
+
+    /// A single atom like `3`, `Ru`, `id:Ru`, `index:3` or `pattern("[C:1]=O", 1)`
+    fn select_one(&mut self) -> Result<SelectOne> {
+        let word = match self.peek() {
+            Some(Token::Quoted(name)) => {
+                let name = name.to_string();
+                self.position += 1;
+                return Ok(SelectOne::IdName(name));
+            }
+            Some(Token::Word(word)) => word.to_string(),
+            _ => Err(self.error("Expected an atom"))?,
+        };
+        let keyword_position = self.position;
+        self.position += 1;
+        match (word.as_str(), self.peek()) {
+            ("id", Some(Token::Colon)) => {
+                self.position += 1;
+                Ok(SelectOne::IdName(self.name()?))
+            }
+            ("index", Some(Token::Colon)) => {
+                self.position += 1;
+                Ok(SelectOne::Index(self.number("Expected an index")?))
+            }
+            ("pattern", Some(Token::Open)) => {
+                self.position += 1;
+                let pattern = self.pattern()?;
+                let map = if self.peek() == Some(&Token::Comma) {
+                    self.position += 1;
+                    self.number("Expected an atom map number")?
+                } else {
+                    1
+                };
+                self.expect(Token::Close)?;
+                Ok(SelectOne::Pattern { pattern, map })
+            }
+            (_, Some(Token::Colon | Token::Open)) => {
+                self.position = keyword_position;
+                Err(self.error("Unknown atom selector"))
+            }
+            _ => Ok(word
+                .parse()
+                .map(SelectOne::Index)
+                .unwrap_or(SelectOne::IdName(word))),
+        }
+    }
+}
+
+/// Parse a textual selector expression. Terms are combined by `&`, `|`, `!` and parentheses,
+/// with terms like `group:bone`, `element:C`, `element:(Cl, 35, transition_metal)`, `id:Ru`,
+/// `index:3`, `12..20`, `all`, `hidden`, `within(Ru, 3.0)`, `within_group(bone, 3.0)`, `neighbors(Ru, 2)`, `fragment(Ru)`,
+/// `side(C1, C2)` and `pattern("[C:1]=O")`. A bare name is a group name, and a bare number
+/// is an index.
+pub fn parse_select_many(source: &str) -> Result<SelectMany> {
+    let mut parser = Parser::new(source)?;
+    let select = parser.or()?;
+    parser.finish()?;
+    Ok(select)
+}
+
+/// Parse a textual atom selector like `3`, `Ru`, `id:Ru`, `index:3` or `pattern("[C:1]=O", 1)`.
+/// A bare name is an id, and a bare number is an index.
+pub fn parse_select_one(source: &str) -> Result<SelectOne> {
+    let mut parser = Parser::new(source)?;
+    let select = parser.select_one()?;
+    parser.finish()?;
+    Ok(select)
+}
+
+#[test]
+fn parse_selector_expressions() {
+    let select = parse_select_many("group:bone & element:C & !id:Ru").unwrap();
+    assert_eq!(
+        select,
+        SelectMany::Complex {
+            includes: vec![SelectMany::Intersect {
+                intersect: vec![
+                    SelectMany::GroupName("bone".to_string()),
+                    SelectMany::Element(6)
+                ]
+            }],
+            excludes: vec![SelectMany::Indexes(BTreeSet::from([SelectOne::IdName(
+                "Ru".to_string()
+            )]))],
+        }
+    );
+    let select = parse_select_many("within(Ru, 3.0) | 12..20").unwrap();
+    assert_eq!(
+        select,
+        SelectMany::Complex {
+            includes: vec![
+                SelectMany::Within {
+                    center: SelectOne::IdName("Ru".to_string()),
+                    radius: 3.
+                },
+                SelectMany::Range(12..=20)
+            ],
+            excludes: vec![],
+        }
+    );
+    assert_eq!(
+        parse_select_many("water2").unwrap(),
+        SelectMany::GroupName("water2".to_string())
+    );
+    assert_eq!(
+        parse_select_one("pattern(\"NC=O\", 2)").unwrap(),
+        SelectOne::Pattern {
+            pattern: "NC=O".to_string(),
+            map: 2
+        }
+    );
+    let error = parse_select_many("group:bone & (within(Ru 3.0)")
+        .unwrap_err()
+        .to_string();
+    assert!(error.starts_with("Expected `,`, found `3.0` at column 25"));
+    assert_eq!(
+        parse_select_many("element:(Cl, Br, transition_metals)").unwrap(),
+        SelectMany::Elements {
+            elements: vec![
+                ElementClass::Element(17),
+                ElementClass::Element(35),
+                ElementClass::TransitionMetal
+            ]
+        }
+    );
+}
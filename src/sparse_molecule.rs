@@ -1,18 +1,23 @@
 use std::{
     collections::{BTreeMap, BTreeSet},
     fs::File,
-    ops::Div,
     path::PathBuf,
 };
 
 use anyhow::Context;
-use bincode::{Decode, Encode};
-use nalgebra::Isometry3;
+use bincode::{
+    de::Decoder,
+    enc::Encoder,
+    error::{DecodeError, EncodeError},
+    impl_borrow_decode, Decode, Encode,
+};
+use nalgebra::{Isometry3, Vector3};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    chemistry::{validated_element_num, Atom3D},
+    chemistry::Atom3D,
     group_name::GroupName,
+    lattice::Lattice,
     layer::{Layer, SelectMany},
 };
 
@@ -33,38 +38,13 @@ impl From<Vec<Atom3D>> for SparseAtomList {
 
 impl Into<Vec<Atom3D>> for SparseAtomList {
     fn into(self) -> Vec<Atom3D> {
-        self.0
-            .into_iter()
-            .filter_map(|atom| {
-                atom.and_then(|atom| {
-                    if validated_element_num(&atom.element) {
-                        Some(atom)
-                    } else {
-                        None
-                    }
-                })
-            })
-            .collect()
+        self.to_continuous_atoms(false)
     }
 }
 
 impl Into<BTreeMap<usize, usize>> for SparseAtomList {
     fn into(self) -> BTreeMap<usize, usize> {
-        self.0
-            .into_iter()
-            .enumerate()
-            .filter_map(|(index, atom)| {
-                atom.and_then(|atom| {
-                    if validated_element_num(atom.element) {
-                        Some(index)
-                    } else {
-                        None
-                    }
-                })
-            })
-            .enumerate()
-            .map(|(continous, sparse)| (sparse, continous))
-            .collect()
+        self.continuous_indexes(false)
     }
 }
 
@@ -90,11 +70,11 @@ impl SparseAtomList {
     }
 
     pub fn read_atom(&self, index: usize) -> Option<Atom3D> {
-        self.0.get(index).copied().unwrap_or_default()
+        self.0.get(index).cloned().unwrap_or_default()
     }
 
     pub fn set_atoms(&mut self, offset: usize, atoms: Vec<Option<Atom3D>>) {
-        let len_after_set = (offset + atoms.len() - 1).max(self.len());
+        let len_after_set = (offset + atoms.len()).max(self.len());
         self.extend_to(len_after_set);
         for (idx, atom) in atoms.into_iter().enumerate() {
             self.0[idx + offset] = atom
@@ -116,26 +96,63 @@ impl SparseAtomList {
     pub fn migrate(&mut self, other: Self) {
         let capacity = self.len().max(other.len());
         self.extend_to(capacity);
-        self.0
-            .iter_mut()
-            .enumerate()
-            .for_each(|(index, atom)| *atom = other.read_atom(index).or(*atom))
+        self.0.iter_mut().zip(other.0).for_each(|(atom, other)| {
+            if other.is_some() {
+                *atom = other
+            }
+        })
     }
 
     pub fn data(&self) -> &Vec<Option<Atom3D>> {
         &self.0
     }
 
-    pub fn update_from_continuous_list(&self, list: &Vec<Atom3D>) -> Option<Self> {
+    fn is_exported(atom: &Option<Atom3D>, with_hidden: bool) -> bool {
+        atom.as_ref()
+            .map(|atom| atom.is_visible() || (with_hidden && atom.is_present()))
+            .unwrap_or_default()
+    }
+
+    /// The atoms to be exported in order, hidden atoms are included only if `with_hidden` is set.
+    pub fn to_continuous_atoms(&self, with_hidden: bool) -> Vec<Atom3D> {
+        self.0
+            .iter()
+            .filter(|atom| Self::is_exported(atom, with_hidden))
+            .flatten()
+            .cloned()
+            .collect()
+    }
+
+    /// Mapping from sparse indexes to continuous indexes of the exported atoms.
+    pub fn continuous_indexes(&self, with_hidden: bool) -> BTreeMap<usize, usize> {
+        self.0
+            .iter()
+            .enumerate()
+            .filter(|(_, atom)| Self::is_exported(atom, with_hidden))
+            .enumerate()
+            .map(|(continous, (sparse, _))| (sparse, continous))
+            .collect()
+    }
+
+    /// Update the atoms with a continuous list read from files, written with or without the
+    /// hidden atoms, which must have exactly the exported atoms. The attributes missing in the
+    /// list, like atom names from a XYZ file, are kept, and so are the elements of hidden atoms
+    /// read back from ghost atoms.
+    pub fn update_from_continuous_list(&self, list: &[Atom3D], with_hidden: bool) -> Option<Self> {
+        let mapping = self.continuous_indexes(with_hidden);
+        if mapping.len() != list.len() {
+            return None;
+        }
         let mut sparse_list = self.clone();
-        let mut wait_to_update = list.iter();
-        for item in sparse_list.0.iter_mut() {
-            if item
-                .map(|atom| validated_element_num(atom.element))
-                .unwrap_or_default()
-            {
-                *item = Some(*wait_to_update.next()?);
+        for (sparse, continuous) in mapping {
+            let atom = sparse_list.0.get_mut(sparse)?.as_mut()?;
+            let updated = &list[continuous];
+            if !atom.hidden {
+                atom.element = updated.element;
             }
+            atom.position = updated.position;
+            atom.formal_charge = updated.formal_charge;
+            atom.merge_attributes(updated);
         }
         Some(sparse_list)
     }
@@ -143,17 +160,14 @@ impl SparseAtomList {
     pub fn to_continuous_index(&self, index: usize) -> Option<usize> {
         if self
             .read_atom(index)
-            .map(|atom| validated_element_num(atom.element))
+            .map(|atom| atom.is_visible())
             .unwrap_or_default()
         {
             Some(
                 self.0
                     .iter()
                     .take(index)
-                    .filter(|item| {
-                        item.map(|item| validated_element_num(item.element))
-                            .unwrap_or_default()
-                    })
+                    .filter(|item| Self::is_exported(item, false))
                     .count(),
             )
         } else {
@@ -165,109 +179,125 @@ impl SparseAtomList {
         self.0
             .iter()
             .enumerate()
-            .filter(|(_, atom)| {
-                atom.map(|atom| validated_element_num(atom.element))
-                    .unwrap_or_default()
-            })
+            .filter(|(_, atom)| Self::is_exported(atom, false))
             .take(index + 1)
             .last()
             .map(|(index, _)| index)
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize, Encode, Decode)]
-pub struct SparseBondMatrix(Vec<Vec<Option<f64>>>);
+/// Bond storage of a sparse molecule.
+///
+/// Only the bonds that have been set are stored, as an adjacency map kept
+/// symmetric for both atoms of the bond. A stored `0.` means the bond is
+/// explicitly removed and will override the bond of a lower layer when migrated,
+/// while a missing item means the bond is not specified in this layer.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(from = "SparseBondMatrixLoader", into = "SparseBondMatrixLoader")]
+pub struct SparseBondMatrix {
+    capacity: usize,
+    bonds: BTreeMap<usize, BTreeMap<usize, f64>>,
+}
 
 impl SparseBondMatrix {
     pub fn new(capacity: usize) -> Self {
-        Self(vec![vec![None; capacity]; capacity])
-    }
-
-    pub fn new_filled(capacity: usize) -> Self {
-        Self(vec![vec![Some(0.); capacity]; capacity])
+        Self {
+            capacity,
+            bonds: BTreeMap::new(),
+        }
     }
 
     pub fn len(&self) -> usize {
-        self.0.len()
+        self.capacity
     }
 
     fn extend_to(&mut self, capacity: usize) {
-        if self.len() < capacity {
-            let current_capacity = self.len();
-            self.0
-                .iter_mut()
-                .for_each(|row| row.extend(&vec![None; capacity - current_capacity]));
-            self.0
-                .append(&mut vec![vec![None; capacity]; capacity - current_capacity]);
-        }
+        self.capacity = self.capacity.max(capacity);
     }
 
     pub fn offset(self, offset: usize) -> Self {
-        let current_capacity = self.len();
-        let prepend_rows = vec![vec![None; offset + current_capacity]; offset];
-        let current_rows = self
-            .0
-            .into_iter()
-            .map(|row| vec![vec![None; offset], row].concat())
-            .collect();
-        Self(vec![prepend_rows, current_rows].concat())
+        Self {
+            capacity: self.capacity + offset,
+            bonds: self
+                .bonds
+                .into_iter()
+                .map(|(a, row)| {
+                    (
+                        a + offset,
+                        row.into_iter()
+                            .map(|(b, bond)| (b + offset, bond))
+                            .collect(),
+                    )
+                })
+                .collect(),
+        }
     }
 
     pub fn read_bond(&self, a: usize, b: usize) -> Option<f64> {
-        self.0.get(a)?.get(b).copied().flatten()
+        self.bonds.get(&a)?.get(&b).copied()
     }
 
-    pub fn get_neighbors(&self, center: usize) -> Option<impl Iterator<Item = &Option<f64>>> {
-        Some(self.0.get(center)?.iter())
+    /// Iterate over the atoms with a bond record to the `center` atom, explicitly
+    /// removed bonds with value `0.` included.
+    pub fn get_neighbors(&self, center: usize) -> impl Iterator<Item = (usize, f64)> + '_ {
+        self.bonds
+            .get(&center)
+            .into_iter()
+            .flat_map(|row| row.iter().map(|(index, bond)| (*index, *bond)))
     }
 
     pub fn set_bond(&mut self, a: usize, b: usize, bond: Option<f64>) {
         self.extend_to(a.max(b) + 1);
-        self.0[a][b] = bond;
-        self.0[b][a] = bond;
+        if let Some(bond) = bond {
+            self.bonds.entry(a).or_default().insert(b, bond);
+            self.bonds.entry(b).or_default().insert(a, bond);
+        } else {
+            for (from, to) in [(a, b), (b, a)] {
+                if let Some(row) = self.bonds.get_mut(&from) {
+                    row.remove(&to);
+                    if row.is_empty() {
+                        self.bonds.remove(&from);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Iterate over each stored bond once as `(a, b, bond)` with `a <= b`.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, usize, f64)> + '_ {
+        self.bonds
+            .iter()
+            .flat_map(|(a, row)| row.range(a..).map(move |(b, bond)| (*a, *b, *bond)))
     }
 
     pub fn migrate(&mut self, other: Self) {
-        for row_idx in 0..other.len() {
-            for col_idx in row_idx..other.len() {
-                let bond = other
-                    .read_bond(row_idx, col_idx)
-                    .or(self.read_bond(row_idx, col_idx));
-                self.set_bond(row_idx, col_idx, bond);
-            }
+        self.extend_to(other.len());
+        for (a, b, bond) in other.iter() {
+            self.set_bond(a, b, Some(bond));
         }
     }
 
     pub fn to_continuous_list(&self, atom_list: &SparseAtomList) -> Vec<(usize, usize, f64)> {
-        let mut continuous_list = Vec::with_capacity(atom_list.len().pow(2).div(2));
-        for row_idx in 0..self.len() {
-            for col_idx in row_idx..self.len() {
-                match (
-                    atom_list.to_continuous_index(row_idx),
-                    atom_list.to_continuous_index(col_idx),
-                    self.read_bond(row_idx, col_idx),
-                ) {
-                    (Some(row_idx), Some(col_idx), Some(bond)) => {
-                        if bond != 0. {
-                            continuous_list.push((row_idx, col_idx, bond));
-                        }
-                    }
-                    _ => {}
-                }
-            }
-        }
-        continuous_list
+        self.to_continuous_list_by(&atom_list.continuous_indexes(false))
+    }
+
+    /// Bonds between the atoms in `mapping`, with indexes converted by it.
+    pub fn to_continuous_list_by(
+        &self,
+        mapping: &BTreeMap<usize, usize>,
+    ) -> Vec<(usize, usize, f64)> {
+        self.iter()
+            .filter_map(|(a, b, bond)| match (mapping.get(&a), mapping.get(&b)) {
+                (Some(a), Some(b)) if bond != 0. => Some((*a, *b, bond)),
+                _ => None,
+            })
+            .collect()
     }
 }
 
 impl<T: Clone + Iterator<Item = ((usize, usize), f64)>> From<T> for SparseBondMatrix {
     fn from(value: T) -> Self {
-        let capacity = value
-            .clone()
-            .map(|((a, b), _)| a.max(b))
-            .max()
-            .unwrap_or_default();
-        let mut bond_matrix = Self::new(capacity);
+        let mut bond_matrix = Self::default();
         for ((a, b), bond) in value {
             bond_matrix.set_bond(a, b, Some(bond));
         }
@@ -275,13 +305,119 @@ impl<T: Clone + Iterator<Item = ((usize, usize), f64)>> From<T> for SparseBondMa
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize, Encode, Decode)]
+/// The bond matrix was stored as a dense `Vec<Vec<Option<f64>>>` in previous
+/// versions, so both the dense and the sparse form are accepted when loading.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum SparseBondMatrixLoader {
+    Dense(Vec<Vec<Option<f64>>>),
+    Sparse {
+        capacity: usize,
+        bonds: Vec<(usize, usize, f64)>,
+    },
+}
+
+impl From<SparseBondMatrixLoader> for SparseBondMatrix {
+    fn from(value: SparseBondMatrixLoader) -> Self {
+        match value {
+            SparseBondMatrixLoader::Dense(rows) => {
+                let mut bond_matrix = Self::new(rows.len());
+                for (a, row) in rows.into_iter().enumerate() {
+                    for (b, bond) in row.into_iter().enumerate() {
+                        if bond.is_some() {
+                            bond_matrix.set_bond(a, b, bond);
+                        }
+                    }
+                }
+                bond_matrix
+            }
+            SparseBondMatrixLoader::Sparse { capacity, bonds } => {
+                let mut bond_matrix = Self::new(capacity);
+                for (a, b, bond) in bonds {
+                    bond_matrix.set_bond(a, b, Some(bond));
+                }
+                bond_matrix
+            }
+        }
+    }
+}
+
+impl From<SparseBondMatrix> for SparseBondMatrixLoader {
+    fn from(value: SparseBondMatrix) -> Self {
+        Self::Sparse {
+            capacity: value.capacity,
+            bonds: value.iter().collect(),
+        }
+    }
+}
+
+/// Leading value of the versioned binary forms of `SparseBondMatrix` and `SparseMolecule`,
+/// followed by the version. The baseline forms start with a length, which never reaches it.
+const SPARSE_VERSION_TAG: u64 = u64::MAX;
+const SPARSE_BOND_MATRIX_VERSION: u8 = 1;
+const SPARSE_MOLECULE_VERSION: u8 = 1;
+
+fn unknown_version(name: &str, version: u8) -> DecodeError {
+    DecodeError::OtherString(format!("Unknown {name} encoding version {version}"))
+}
+
+impl Encode for SparseBondMatrix {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        SPARSE_VERSION_TAG.encode(encoder)?;
+        SPARSE_BOND_MATRIX_VERSION.encode(encoder)?;
+        self.capacity.encode(encoder)?;
+        self.iter().collect::<Vec<_>>().encode(encoder)
+    }
+}
+
+impl Decode for SparseBondMatrix {
+    fn decode<D: Decoder>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let leading = u64::decode(decoder)?;
+        if leading != SPARSE_VERSION_TAG {
+            let rows =
+                usize::try_from(leading).map_err(|_| DecodeError::OutsideUsizeRange(leading))?;
+            let mut bond_matrix = Self::new(rows);
+            for a in 0..rows {
+                let row = Vec::<Option<f64>>::decode(decoder)?;
+                for (b, bond) in row.into_iter().enumerate() {
+                    if bond.is_some() {
+                        bond_matrix.set_bond(a, b, bond);
+                    }
+                }
+            }
+            return Ok(bond_matrix);
+        }
+        match u8::decode(decoder)? {
+            SPARSE_BOND_MATRIX_VERSION => {
+                let capacity = usize::decode(decoder)?;
+                let bonds = Vec::<(usize, usize, f64)>::decode(decoder)?;
+                Ok(SparseBondMatrixLoader::Sparse { capacity, bonds }.into())
+            }
+            version => Err(unknown_version("SparseBondMatrix", version)),
+        }
+    }
+}
+
+impl_borrow_decode!(SparseBondMatrix);
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
 #[serde(try_from = "SparseMoleculeLoader")]
 pub struct SparseMolecule {
     pub atoms: SparseAtomList,
     pub bonds: SparseBondMatrix,
     pub ids: Option<BTreeMap<String, usize>>,
     pub groups: Option<GroupName>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lattice: Option<Lattice>,
+    /// Total charge of the molecule, summed from formal charges if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub charge: Option<i64>,
+    /// Spin multiplicity, the lowest one for the electron count is used if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub multiplicity: Option<usize>,
+    /// Scalar results like energies read from calculation outputs, in atomic units
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub properties: BTreeMap<String, f64>,
 }
 
 impl SparseMolecule {
@@ -309,6 +445,133 @@ impl SparseMolecule {
             }
             _ => self.groups = self.groups.clone().or(other.groups.clone()),
         }
+        self.lattice = other.lattice.or(self.lattice);
+        self.charge = other.charge.or(self.charge);
+        self.multiplicity = other.multiplicity.or(self.multiplicity);
+        self.properties.extend(other.properties);
+    }
+
+    /// Total charge of the molecule, the sum of formal charges of visible atoms is used
+    /// if it is not set explicitly
+    pub fn total_charge(&self) -> i64 {
+        self.charge.unwrap_or_else(|| {
+            self.atoms
+                .to_continuous_atoms(false)
+                .iter()
+                .map(|atom| atom.formal_charge)
+                .sum::<f64>()
+                .round() as i64
+        })
+    }
+
+    /// Spin multiplicity of the molecule, singlet or doublet by the count of electrons
+    /// of visible atoms if it is not set explicitly
+    pub fn spin_multiplicity(&self) -> usize {
+        self.multiplicity.unwrap_or_else(|| {
+            let electrons = self
+                .atoms
+                .to_continuous_atoms(false)
+                .iter()
+                .map(|atom| atom.element as i64)
+                .sum::<i64>()
+                - self.total_charge();
+            if electrons % 2 == 0 {
+                1
+            } else {
+                2
+            }
+        })
+    }
+
+    /// Vector from one atom to another, taking the minimum image if the structure is periodic
+    pub fn displacement(&self, from: usize, to: usize) -> Option<Vector3<f64>> {
+        let from = self.atoms.read_atom(from)?;
+        let to = self.atoms.read_atom(to)?;
+        let vector = to.position - from.position;
+        Some(match &self.lattice {
+            Some(lattice) => lattice.minimum_image(vector),
+            None => vector,
+        })
+    }
+
+    /// Present atoms bonded to the atom, bonds removed with value `0` are skipped
+    pub fn bonded_atoms(&self, center: usize) -> impl Iterator<Item = usize> + '_ {
+        self.bonds
+            .get_neighbors(center)
+            .filter(|(_, bond)| *bond != 0.)
+            .map(|(index, _)| index)
+            .filter(|index| {
+                self.atoms
+                    .read_atom(*index)
+                    .is_some_and(|atom| atom.is_present())
+            })
+    }
+
+    /// Atoms connected to the entry atom by bonds, the atoms in `excludes` are never
+    /// entered. The entry atom is included if it is present.
+    pub fn connected_atoms(&self, entry: usize, excludes: &BTreeSet<usize>) -> BTreeSet<usize> {
+        let mut visited = BTreeSet::new();
+        if !self
+            .atoms
+            .read_atom(entry)
+            .is_some_and(|atom| atom.is_present())
+        {
+            return visited;
+        }
+        let mut stack = vec![entry];
+        while let Some(current) = stack.pop() {
+            if excludes.contains(&current) || !visited.insert(current) {
+                continue;
+            }
+            stack.extend(self.bonded_atoms(current));
+        }
+        visited
+    }
+
+    /// Atoms in the same fragment of the given atom
+    pub fn fragment(&self, index: usize) -> BTreeSet<usize> {
+        self.connected_atoms(index, &BTreeSet::new())
+    }
+
+    /// Atoms on the `to` side of the bond between `from` and `to`. If the bond is in a
+    /// ring, all atoms of the fragment except `from` are returned.
+    pub fn side(&self, from: usize, to: usize) -> BTreeSet<usize> {
+        self.connected_atoms(to, &BTreeSet::from([from]))
+    }
+
+    /// Present atoms within `radius` of any of the `centers`, using minimum image distances
+    /// for periodic structures
+    pub fn atoms_within(&self, centers: &BTreeSet<usize>, radius: f64) -> BTreeSet<usize> {
+        (0..self.len())
+            .filter(|index| {
+                self.atoms
+                    .read_atom(*index)
+                    .is_some_and(|atom| atom.is_present())
+            })
+            .filter(|index| {
+                centers.iter().any(|center| {
+                    self.displacement(*center, *index)
+                        .is_some_and(|vector| vector.norm() <= radius)
+                })
+            })
+            .collect()
+    }
+
+    /// Connected components of the molecular graph, ordered by their smallest atom index
+    pub fn connected_components(&self) -> Vec<BTreeSet<usize>> {
+        let mut assigned = BTreeSet::new();
+        let mut components = vec![];
+        for index in 0..self.len() {
+            if assigned.contains(&index) {
+                continue;
+            }
+            let component = self.fragment(index);
+            if !component.is_empty() {
+                assigned.extend(component.iter().copied());
+                components.push(component);
+            }
+        }
+        components
     }
 
     pub fn offset(self, offset: usize) -> Self {
@@ -331,12 +594,111 @@ impl SparseMolecule {
             bonds,
             ids,
             groups,
+            lattice: self.lattice,
+            charge: self.charge,
+            multiplicity: self.multiplicity,
+            properties: self.properties,
+        }
+    }
+
+    /// Renumber atoms so that the atom at `order[i]` becomes the `i`th atom. Atoms not in
+    /// `order` are dropped, with their bonds, ids and groups.
+    pub fn reindex(self, order: &[usize]) -> Self {
+        let mapping = order
+            .iter()
+            .enumerate()
+            .map(|(new, old)| (*old, new))
+            .collect::<BTreeMap<_, _>>();
+        let atoms = SparseAtomList::from(
+            order
+                .iter()
+                .map(|index| self.atoms.read_atom(*index))
+                .collect::<Vec<_>>(),
+        );
+        let mut bonds = SparseBondMatrix::new(order.len());
+        for (a, b, bond) in self.bonds.iter() {
+            if let (Some(a), Some(b)) = (mapping.get(&a), mapping.get(&b)) {
+                bonds.set_bond(*a, *b, Some(bond));
+            }
+        }
+        let ids = self.ids.map(|ids| {
+            ids.into_iter()
+                .filter_map(|(id, idx)| Some((id, *mapping.get(&idx)?)))
+                .collect()
+        });
+        let groups = self.groups.map(|groups| {
+            GroupName::from_iter(
+                groups
+                    .into_iter()
+                    .filter_map(|(group_name, idx)| Some((group_name, *mapping.get(&idx)?))),
+            )
+        });
+        Self {
+            atoms,
+            bonds,
+            ids,
+            groups,
+            lattice: self.lattice,
+            charge: self.charge,
+            multiplicity: self.multiplicity,
+            properties: self.properties,
         }
     }
 }
 
+impl Encode for SparseMolecule {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        SPARSE_VERSION_TAG.encode(encoder)?;
+        SPARSE_MOLECULE_VERSION.encode(encoder)?;
+        self.atoms.encode(encoder)?;
+        self.bonds.encode(encoder)?;
+        self.ids.encode(encoder)?;
+        self.groups.encode(encoder)?;
+        self.lattice.encode(encoder)?;
+        self.charge.encode(encoder)?;
+        self.multiplicity.encode(encoder)?;
+        self.properties.encode(encoder)
+    }
+}
+
+impl Decode for SparseMolecule {
+    fn decode<D: Decoder>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let leading = u64::decode(decoder)?;
+        if leading != SPARSE_VERSION_TAG {
+            let length =
+                usize::try_from(leading).map_err(|_| DecodeError::OutsideUsizeRange(leading))?;
+            let atoms = (0..length)
+                .map(|_| Option::<Atom3D>::decode(decoder))
+                .collect::<Result<Vec<_>, _>>()?;
+            return Ok(Self {
+                atoms: SparseAtomList::from(atoms),
+                bonds: Decode::decode(decoder)?,
+                ids: Decode::decode(decoder)?,
+                groups: Decode::decode(decoder)?,
+                ..Default::default()
+            });
+        }
+        match u8::decode(decoder)? {
+            SPARSE_MOLECULE_VERSION => Ok(Self {
+                atoms: Decode::decode(decoder)?,
+                bonds: Decode::decode(decoder)?,
+                ids: Decode::decode(decoder)?,
+                groups: Decode::decode(decoder)?,
+                lattice: Decode::decode(decoder)?,
+                charge: Decode::decode(decoder)?,
+                multiplicity: Decode::decode(decoder)?,
+                properties: Decode::decode(decoder)?,
+            }),
+            version => Err(unknown_version("SparseMolecule", version)),
+        }
+    }
+}
+
+impl_borrow_decode!(SparseMolecule);
+
 #[derive(Deserialize)]
 #[serde(untagged)]
+#[allow(clippy::large_enum_variant)]
 enum SparseMoleculeLoader {
     FilePath(PathBuf),
     Data {
@@ -346,6 +708,14 @@ enum SparseMoleculeLoader {
         ids: Option<BTreeMap<String, usize>>,
         #[serde(default)]
         groups: Option<GroupName>,
+        #[serde(default)]
+        lattice: Option<Lattice>,
+        #[serde(default)]
+        charge: Option<i64>,
+        #[serde(default)]
+        multiplicity: Option<usize>,
+        #[serde(default)]
+        properties: BTreeMap<String, f64>,
     },
     Component(Vec<SparseMoleculeComponent>),
 }
@@ -386,11 +756,19 @@ impl TryFrom<SparseMoleculeLoader> for SparseMolecule {
                 bonds,
                 ids,
                 groups,
+                lattice,
+                charge,
+                multiplicity,
+                properties,
             } => Ok(Self {
                 atoms,
                 bonds,
                 ids,
                 groups,
+                lattice,
+                charge,
+                multiplicity,
+                properties,
             }),
             SparseMoleculeLoader::FilePath(path) => {
                 let file = File::open(&path).with_context(|| {
@@ -409,3 +787,138 @@ impl TryFrom<SparseMoleculeLoader> for SparseMolecule {
         }
     }
 }
+
+#[test]
+fn load_dense_bond_matrix() {
+    let dense = vec![
+        vec![None, Some(1.), Some(1.)],
+        vec![Some(1.), None, None],
+        vec![Some(1.), None, None],
+    ];
+    let encoded = bincode::encode_to_vec(&dense, bincode::config::standard()).unwrap();
+    let (from_bincode, _): (SparseBondMatrix, _) =
+        bincode::decode_from_slice(&encoded, bincode::config::standard()).unwrap();
+    let from_json: SparseBondMatrix =
+        serde_json::from_str(&serde_json::to_string(&dense).unwrap()).unwrap();
+    assert_eq!(from_bincode, from_json);
+    assert_eq!(from_bincode.len(), 3);
+    assert_eq!(from_bincode.read_bond(2, 0), Some(1.));
+    assert_eq!(from_bincode.read_bond(1, 2), None);
+}
+
+#[test]
+fn sparse_bond_matrix_round_trip() {
+    let mut bonds = SparseBondMatrix::new(4);
+    bonds.set_bond(0, 3, Some(2.));
+    bonds.set_bond(1, 2, Some(0.));
+    let bonds = bonds.offset(2);
+    let encoded = bincode::encode_to_vec(&bonds, bincode::config::standard()).unwrap();
+    let (decoded, _): (SparseBondMatrix, _) =
+        bincode::decode_from_slice(&encoded, bincode::config::standard()).unwrap();
+    assert_eq!(decoded, bonds);
+    let json: SparseBondMatrix =
+        serde_json::from_str(&serde_json::to_string(&bonds).unwrap()).unwrap();
+    assert_eq!(json, bonds);
+    assert_eq!(json.len(), 6);
+    assert_eq!(json.read_bond(5, 2), Some(2.));
+    assert_eq!(json.get_neighbors(3).collect::<Vec<_>>(), vec![(4, 0.)]);
+}
+
+#[test]
+fn update_with_ghost_atoms() {
+    let atoms = SparseAtomList::from(
+        [(8, false), (1, true), (1, false)]
+            .map(|(element, hidden)| {
+                Some(Atom3D {
+                    element,
+                    hidden,
+                    ..Default::default()
+                })
+            })
+            .to_vec(),
+    );
+    let mut exported = atoms.to_continuous_atoms(true);
+    for (index, atom) in exported.iter_mut().enumerate() {
+        atom.position.x = index as f64;
+    }
+    let xyz = crate::io::BasicIOMolecule::new(String::new(), exported, vec![])
+        .output("xyz")
+        .unwrap();
+    let read = crate::io::BasicIOMolecule::input("xyz", xyz.as_bytes()).unwrap();
+    assert!(read.atoms[1].hidden);
+    let updated = atoms
+        .update_from_continuous_list(&read.atoms, true)
+        .unwrap();
+    let updated: Vec<Atom3D> = updated.to_continuous_atoms(true);
+    assert_eq!(updated[2].position.x, 2.);
+    assert!(updated[1].hidden);
+    assert!(atoms
+        .update_from_continuous_list(&read.atoms, false)
+        .is_none());
+}
+
+#[test]
+fn decode_baseline_molecule() {
+    #[derive(Encode)]
+    struct BaselineAtom3D {
+        element: usize,
+        #[bincode(with_serde)]
+        position: nalgebra::Point3<f64>,
+        formal_charge: f64,
+    }
+    #[derive(Encode)]
+    struct BaselineSparseMolecule {
+        atoms: Vec<Option<BaselineAtom3D>>,
+        bonds: Vec<Vec<Option<f64>>>,
+        ids: Option<BTreeMap<String, usize>>,
+        groups: Option<GroupName>,
+    }
+    let baseline = BaselineSparseMolecule {
+        atoms: vec![
+            Some(BaselineAtom3D {
+                element: 8,
+                position: nalgebra::Point3::new(0., 0., 1.),
+                formal_charge: 0.,
+            }),
+            None,
+            Some(BaselineAtom3D {
+                element: 1 + 128,
+                position: nalgebra::Point3::origin(),
+                formal_charge: 0.,
+            }),
+        ],
+        bonds: vec![
+            vec![None, None, Some(1.)],
+            vec![None, None, None],
+            vec![Some(1.), None, None],
+        ],
+        ids: Some(BTreeMap::from([("O".to_string(), 0)])),
+        groups: None,
+    };
+    let encoded = bincode::encode_to_vec(baseline, bincode::config::standard()).unwrap();
+    let (molecule, _): (SparseMolecule, _) =
+        bincode::decode_from_slice(&encoded, bincode::config::standard()).unwrap();
+    assert_eq!(molecule.len(), 3);
+    assert_eq!(molecule.atoms.read_atom(0).unwrap().position.z, 1.);
+    assert!(molecule.atoms.read_atom(1).is_none());
+    let hidden = molecule.atoms.read_atom(2).unwrap();
+    assert_eq!((hidden.element, hidden.hidden), (1, true));
+    assert_eq!(molecule.bonds.read_bond(0, 2), Some(1.));
+    assert_eq!(molecule.ids, Some(BTreeMap::from([("O".to_string(), 0)])));
+    assert_eq!((molecule.charge, molecule.lattice), (None, None));
+    let molecule = SparseMolecule {
+        charge: Some(-1),
+        multiplicity: Some(2),
+        ..molecule
+    };
+    let encoded = bincode::encode_to_vec(&molecule, bincode::config::standard()).unwrap();
+    let (decoded, _): (SparseMolecule, _) =
+        bincode::decode_from_slice(&encoded, bincode::config::standard()).unwrap();
+    assert_eq!(decoded, molecule);
+    let mut future = encoded;
+    future[9] = SPARSE_MOLECULE_VERSION + 1;
+    assert!(
+        bincode::decode_from_slice::<SparseMolecule, _>(&future, bincode::config::standard())
+            .is_err()
+    );
+}
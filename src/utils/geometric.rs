@@ -1,4 +1,6 @@
-use nalgebra::{Unit, Vector3};
+use nalgebra::{
+    Isometry3, Matrix3, Point3, Rotation3, Translation3, Unit, UnitQuaternion, Vector3,
+};
 
 pub fn axis_angle_for_b2a(a: Vector3<f64>, b: Vector3<f64>) -> (Unit<Vector3<f64>>, f64) {
     let axis = b.cross(&a);
@@ -16,6 +18,61 @@ pub fn axis_angle_for_b2a(a: Vector3<f64>, b: Vector3<f64>) -> (Unit<Vector3<f64
     (axis, angle)
 }
 
+/// The isometry superposing `from` points onto `to` points with the least root mean square
+/// deviation by the Kabsch algorithm, `None` for empty or unequal lists
+pub fn kabsch(from: &[Point3<f64>], to: &[Point3<f64>]) -> Option<Isometry3<f64>> {
+    if from.is_empty() || from.len() != to.len() {
+        return None;
+    }
+    let centroid = |points: &[Point3<f64>]| {
+        Point3::from(
+            points
+                .iter()
+                .map(|point| point.coords)
+                .sum::<Vector3<f64>>()
+                / points.len() as f64,
+        )
+    };
+    let (from_center, to_center) = (centroid(from), centroid(to));
+    let covariance = from.iter().zip(to).fold(Matrix3::zeros(), |acc, (a, b)| {
+        acc + (a - from_center) * (b - to_center).transpose()
+    });
+    let svd = covariance.svd(true, true);
+    let (u, v_t) = (svd.u?, svd.v_t?);
+    // Avoid reflections by flipping the axis with the smallest singular value
+    let d = (v_t.transpose() * u.transpose()).determinant().signum();
+    let rotation =
+        v_t.transpose() * Matrix3::from_diagonal(&Vector3::new(1., 1., d)) * u.transpose();
+    let rotation =
+        UnitQuaternion::from_rotation_matrix(&Rotation3::from_matrix_unchecked(rotation));
+    let translation = Translation3::from(to_center.coords - rotation * from_center.coords);
+    Some(Isometry3::from_parts(translation, rotation))
+}
+
+/// A unit vector perpendicular to the given one
+pub fn perpendicular(vector: Vector3<f64>) -> Unit<Vector3<f64>> {
+    let axis = [Vector3::x(), Vector3::y(), Vector3::z()]
+        .into_iter()
+        .map(|axis| vector.cross(&axis))
+        .max_by(|a, b| a.norm().total_cmp(&b.norm()))
+        .unwrap_or_default();
+    // Any direction is perpendicular to the zero vector
+    Unit::try_new(axis, 1e-12).unwrap_or(Vector3::x_axis())
+}
+
+/// Angle a-b-c in radian
+pub fn angle(a: Point3<f64>, b: Point3<f64>, c: Point3<f64>) -> f64 {
+    (a - b).angle(&(c - b))
+}
+
+/// Dihedral angle a-b-c-d in radian, from -π to π
+pub fn dihedral(a: Point3<f64>, b: Point3<f64>, c: Point3<f64>, d: Point3<f64>) -> f64 {
+    let (ba, cb, dc) = (b - a, c - b, d - c);
+    let n1 = ba.cross(&cb);
+    let n2 = cb.cross(&dc);
+    (cb.norm() * ba.dot(&n2)).atan2(n1.dot(&n2))
+}
+
 #[test]
 fn reverse_vectors() {
     println!(
@@ -47,3 +104,26 @@ fn zero_vector() {
         axis_angle_for_b2a(Vector3::new(1., 0., 0.), Vector3::new(0., 0., 0.))
     )
 }
+
+#[test]
+fn dihedral_sign() {
+    let value = dihedral(
+        Point3::new(1., 0., 0.),
+        Point3::origin(),
+        Point3::new(0., 0., 1.),
+        Point3::new(0., 1., 1.),
+    );
+    assert!((value - std::f64::consts::FRAC_PI_2).abs() < 1e-10);
+}
+
+#[test]
+fn kabsch_superposition() {
+    let from = [(0., 0., 0.), (1., 0., 0.), (0., 2., 0.), (0., 0., 3.)]
+        .map(|(x, y, z)| Point3::new(x, y, z));
+    let isometry = Isometry3::new(Vector3::new(1., -2., 0.5), Vector3::new(0.3, -1.2, 0.7));
+    let to = from.map(|point| isometry * point);
+    let fitted = kabsch(&from, &to).unwrap();
+    for (a, b) in from.iter().zip(&to) {
+        assert!((fitted * a - b).norm() < 1e-10);
+    }
+}
@@ -0,0 +1,175 @@
+use nalgebra::Vector3;
+
+use crate::{
+    elements::{periodic_group, valences},
+    utils::geometric::perpendicular,
+};
+
+/// Number of hydrogens to saturate the lowest typical valence not less than the sum of bond
+/// orders. The valence is corrected by the formal charge, like 4 for N+ and 1 for O-.
+/// Hydrogen atoms and elements without typical valences get no hydrogens.
+pub fn missing_hydrogens(element: usize, bond_orders: f64, formal_charge: f64) -> usize {
+    if element == 1 {
+        return 0;
+    }
+    let (bond_orders, charge) = (bond_orders.round() as i64, formal_charge.round() as i64);
+    valences(element)
+        .into_iter()
+        .map(|valence| {
+            let valence = valence as i64;
+            match periodic_group(element) {
+                Some(13) => valence - charge,
+                Some(14) => valence - charge.abs(),
+                Some(15..=17) => valence + charge,
+                _ => valence,
+            }
+        })
+        .find(|valence| *valence >= bond_orders)
+        .map(|valence| (valence - bond_orders) as usize)
+        .unwrap_or_default()
+}
+
+/// Like `missing_hydrogens` from the orders of each bond. Aromatic bonds of order 1.5 are
+/// read as single bonds with one more double bond or as all single bonds, whichever needs
+/// fewer hydrogens, so atoms like N in pyrrole and S in thiophene get no more hydrogens.
+pub fn missing_hydrogens_by_bonds(element: usize, orders: &[f64], formal_charge: f64) -> usize {
+    let aromatic = orders.iter().filter(|order| **order == 1.5).count();
+    let others = orders.iter().filter(|order| **order != 1.5).sum::<f64>();
+    let extras: &[f64] = if aromatic > 0 { &[0., 1.] } else { &[0.] };
+    extras
+        .iter()
+        .map(|extra| missing_hydrogens(element, others + aromatic as f64 + extra, formal_charge))
+        .min()
+        .unwrap_or_default()
+}
+
+/// Number of electron domains from the bond orders of an atom, 2 for sp atoms with a triple
+/// bond or two double bonds, 3 for sp2 atoms with a double or aromatic bond, 4 otherwise.
+pub fn electron_domains(bond_orders: &[f64]) -> usize {
+    let multiple = bond_orders.iter().filter(|order| **order > 1.).count();
+    if bond_orders.iter().any(|order| *order >= 3.)
+        || (multiple >= 2 && bond_orders.iter().all(|order| *order >= 2.))
+    {
+        2
+    } else if multiple > 0 {
+        3
+    } else {
+        4
+    }
+}
+
+/// Unit vectors of the free positions around an atom with `domains` electron domains, given
+/// the unit vectors to the bonded atoms. For atoms with one neighbor, `reference` is a vector
+/// from the neighbor to one of its other neighbors, the new bonds are put in the same plane
+/// for sp2 atoms, and staggered for sp3 atoms.
+pub fn free_directions(
+    neighbors: &[Vector3<f64>],
+    reference: Option<Vector3<f64>>,
+    domains: usize,
+) -> Vec<Vector3<f64>> {
+    let cos: f64 = match domains {
+        2 => -1.,
+        3 => -0.5,
+        _ => -1. / 3.,
+    };
+    let sin = (1. - cos * cos).sqrt();
+    match neighbors {
+        [] => match domains {
+            2 => vec![Vector3::x(), -Vector3::x()],
+            3 => vec![
+                Vector3::x(),
+                Vector3::new(cos, sin, 0.),
+                Vector3::new(cos, -sin, 0.),
+            ],
+            _ => [(1., 1., 1.), (1., -1., -1.), (-1., 1., -1.), (-1., -1., 1.)]
+                .map(|(x, y, z)| Vector3::new(x, y, z).normalize())
+                .to_vec(),
+        },
+        [u] => {
+            let p = reference
+                .map(|reference| reference - u * u.dot(&reference))
+                .filter(|p| p.norm() > 1e-8)
+                .map(|p| p.normalize())
+                .unwrap_or_else(|| perpendicular(*u).into_inner());
+            match domains {
+                2 => vec![-u],
+                3 => vec![u * cos - p * sin, u * cos + p * sin],
+                _ => {
+                    // One bond anti to the reference, the others rotated by 120° around u
+                    let q = u.cross(&p);
+                    let half = 3f64.sqrt() / 2.;
+                    vec![
+                        u * cos - p * sin,
+                        u * cos + (p * 0.5 + q * half) * sin,
+                        u * cos + (p * 0.5 - q * half) * sin,
+                    ]
+                }
+            }
+        }
+        [u1, u2] if domains > 2 => {
+            let bisector = -(u1 + u2);
+            let bisector = if bisector.norm() > 1e-8 {
+                bisector.normalize()
+            } else {
+                perpendicular(*u1).into_inner()
+            };
+            if domains == 3 {
+                return vec![bisector];
+            }
+            let normal = u1.cross(u2);
+            // Neighbors in a line or on top of each other have no plane, take any normal
+            let normal = normal
+                .try_normalize(1e-8)
+                .or_else(|| u1.cross(&bisector).try_normalize(1e-8))
+                .unwrap_or_else(|| perpendicular(bisector).into_inner());
+            // Half of the tetrahedral angle between the new bonds
+            let (cos, sin) = ((1. / 3f64).sqrt(), (2. / 3f64).sqrt());
+            vec![bisector * cos + normal * sin, bisector * cos - normal * sin]
+        }
+        [u1, u2, u3] if domains > 3 => {
+            let direction = -(u1 + u2 + u3);
+            if direction.norm() > 1e-8 {
+                vec![direction.normalize()]
+            } else {
+                vec![u1
+                    .cross(u2)
+                    .try_normalize(1e-8)
+                    .unwrap_or_else(|| perpendicular(*u1).into_inner())]
+            }
+        }
+        _ => vec![],
+    }
+}
+
+#[test]
+fn hydrogen_geometry() {
+    assert_eq!(missing_hydrogens(6, 0., 0.), 4);
+    assert_eq!(missing_hydrogens(7, 0., 1.), 4);
+    assert_eq!(missing_hydrogens(8, 1., -1.), 0);
+    assert_eq!(missing_hydrogens(6, 3., 0.), 1);
+    assert_eq!(missing_hydrogens_by_bonds(6, &[1.5, 1.5], 0.), 1);
+    assert_eq!(missing_hydrogens_by_bonds(16, &[1.5, 1.5], 0.), 0);
+    assert_eq!(missing_hydrogens_by_bonds(7, &[1.5, 1.5, 1.], 0.), 0);
+    assert_eq!(electron_domains(&[1.5, 1.5]), 3);
+    assert_eq!(electron_domains(&[2., 2.]), 2);
+    let u = Vector3::x();
+    let reference = Some(Vector3::y());
+    let directions = free_directions(&[u], reference, 4);
+    assert_eq!(directions.len(), 3);
+    for (i, a) in directions.iter().enumerate() {
+        assert!((a.norm() - 1.).abs() < 1e-10);
+        assert!((a.dot(&u) + 1. / 3.).abs() < 1e-10);
+        for b in &directions[i + 1..] {
+            assert!((a.dot(b) + 1. / 3.).abs() < 1e-10);
+        }
+    }
+    assert!(directions[0].y < 0.);
+    let directions = free_directions(&[u, Vector3::new(-0.5, 0.75f64.sqrt(), 0.)], None, 3);
+    assert!((directions[0] - Vector3::new(-0.5, -(0.75f64.sqrt()), 0.)).norm() < 1e-10);
+    for neighbors in [[u, u], [u, -u]] {
+        let directions = free_directions(&neighbors, None, 4);
+        assert!(directions.iter().flatten().all(|value| value.is_finite()));
+    }
+    let directions = free_directions(&[u, u, -u], None, 4);
+    assert!(directions.iter().flatten().all(|value| value.is_finite()));
+}
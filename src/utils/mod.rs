@@ -1,3 +1,6 @@
 pub mod fs;
 pub mod geometric;
+pub mod hydrogens;
+pub mod perception;
+pub mod smarts;
 pub mod sterimol;
@@ -0,0 +1,463 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use crate::{
+    chemistry::Atom3D,
+    elements::{periodic_group, valences, RadiiSet},
+};
+
+/// Atoms closer than the sum of covalent radii plus the tolerance in Angstrom are bonded
+const BOND_TOLERANCE: f64 = 0.4;
+
+/// Elements from B to F, which never exceed the octet
+fn obeys_octet(element: usize) -> bool {
+    (5..=9).contains(&element)
+}
+
+fn is_chalcogen(element: usize) -> bool {
+    element == 8 || element == 16
+}
+
+/// The valence of the atom to saturate. Atoms bonded to terminal O and S atoms may use
+/// a higher valence like S in sulfones, and the octet atoms may take one more bond
+/// like N in ammonium. `None` for elements without a typical valence.
+fn target_valence(element: usize, degree: usize, terminal_chalcogens: usize) -> Option<usize> {
+    let mut options = valences(element);
+    let lowest = *options.first()?;
+    if obeys_octet(element) {
+        options = vec![lowest, (lowest + 1).min(4)];
+        // Nitro groups and N-oxides are assigned as N(=O) first and separated later
+        if element == 7 && terminal_chalcogens > 0 {
+            options.push(5);
+        }
+    }
+    let candidates = options.iter().copied().filter(|valence| *valence >= degree);
+    candidates
+        .clone()
+        .filter(|valence| *valence <= degree + terminal_chalcogens)
+        .max()
+        .or(candidates.min())
+        .or(Some(degree))
+}
+
+/// Formal charge of the atom from the sum of bond orders
+fn formal_charge(element: usize, bond_orders: usize) -> f64 {
+    let options = valences(element);
+    let Some(&lowest) = options.first() else {
+        return 0.;
+    };
+    let (bond_orders, lowest) = (bond_orders as f64, lowest as f64);
+    if options.contains(&(bond_orders as usize)) {
+        return 0.;
+    }
+    match (element, periodic_group(element)) {
+        (6 | 1, _) => 0.,
+        (5, _) if bond_orders > lowest => lowest - bond_orders,
+        (_, Some(1 | 2 | 13)) if bond_orders < lowest => lowest - bond_orders,
+        (_, Some(15..=17)) if bond_orders < lowest => bond_orders - lowest,
+        (_, Some(15..=17)) => {
+            let below = options
+                .iter()
+                .filter(|valence| (**valence as f64) < bond_orders)
+                .max()
+                .copied()
+                .unwrap_or_default();
+            bond_orders - below as f64
+        }
+        _ => 0.,
+    }
+}
+
+/// Smallest rings with 5 to 7 atoms, found by the shortest cycle through each bond
+fn small_rings(neighbors: &[BTreeMap<usize, usize>]) -> BTreeSet<Vec<usize>> {
+    let mut rings = BTreeSet::new();
+    for (a, bonded) in neighbors.iter().enumerate() {
+        for &b in bonded.keys().filter(|b| **b > a) {
+            // Breadth first search from b to a without the bond a-b
+            let mut previous = BTreeMap::from([(b, b)]);
+            let mut queue = VecDeque::from([(b, 1)]);
+            while let Some((current, depth)) = queue.pop_front() {
+                if current == a || depth >= 7 {
+                    continue;
+                }
+                for &next in neighbors[current].keys() {
+                    if (current == b && next == a) || previous.contains_key(&next) {
+                        continue;
+                    }
+                    previous.insert(next, current);
+                    queue.push_back((next, depth + 1));
+                }
+            }
+            if previous.contains_key(&a) {
+                let mut ring = vec![a];
+                let mut current = a;
+                while current != b {
+                    current = previous[&current];
+                    ring.push(current);
+                }
+                if (5..=7).contains(&ring.len()) {
+                    ring.sort();
+                    rings.insert(ring);
+                }
+            }
+        }
+    }
+    rings
+}
+
+/// Electrons given to the π system of a ring by an atom, 1 for a double bond in the ring,
+/// 0 for an exocyclic double bond or an empty p orbital, and 2 for a lone pair like N in
+/// pyrrole. `None` for saturated atoms like sp3 carbons.
+fn pi_electrons(element: usize, charge: f64, double_in_ring: bool, double: bool) -> Option<usize> {
+    if double_in_ring {
+        return Some(1);
+    }
+    if double {
+        return Some(0);
+    }
+    match element {
+        7 | 8 | 15 | 16 | 34 if charge <= 0. => Some(2),
+        6 if charge < 0. => Some(2),
+        6 if charge > 0. => Some(0),
+        5 => Some(0),
+        _ => None,
+    }
+}
+
+/// Pairs of atoms `(a, b)` with `a < b` which may be bonded, found in a grid of cells as
+/// large as the longest possible bond
+fn close_pairs(atoms: &[Atom3D], radii: &[Option<f64>]) -> Vec<(usize, usize)> {
+    let Some(largest) = radii.iter().flatten().copied().reduce(f64::max) else {
+        return vec![];
+    };
+    let size = 2. * largest + BOND_TOLERANCE;
+    let cell_of = |atom: &Atom3D| {
+        atom.position
+            .coords
+            .map(|x| (x / size).floor() as i64)
+            .into()
+    };
+    let mut cells = BTreeMap::<[i64; 3], Vec<usize>>::new();
+    for (index, atom) in atoms.iter().enumerate() {
+        if radii[index].is_some() {
+            cells.entry(cell_of(atom)).or_default().push(index);
+        }
+    }
+    let mut pairs = vec![];
+    for (cell, members) in &cells {
+        for offset in (0..27).map(|i| [i % 3 - 1, i / 3 % 3 - 1, i / 9 - 1]) {
+            let [x, y, z] = *cell;
+            let Some(others) = cells.get(&[x + offset[0], y + offset[1], z + offset[2]]) else {
+                continue;
+            };
+            for a in members {
+                pairs.extend(others.iter().filter(|b| *b > a).map(|b| (*a, *b)));
+            }
+        }
+    }
+    pairs
+}
+
+/// Perceive bonds, bond orders and formal charges from 3D coordinates. Bond orders are
+/// assigned to saturate the typical valences of atoms, preferring shorter bonds, and
+/// rings of 5 to 7 atoms with 4n+2 π electrons by the Hückel rule, like benzene, pyrrole,
+/// furan and thiophene, are marked as aromatic with order 1.5.
+///
+/// Returns bonds like `(a, b, order)` with `a < b` and the formal charges of atoms.
+pub fn perceive_bonds(atoms: &[Atom3D]) -> (Vec<(usize, usize, f64)>, Vec<f64>) {
+    let radii = atoms
+        .iter()
+        .map(|atom| RadiiSet::Covalent.radius(atom.element))
+        .collect::<Vec<_>>();
+    // Bond orders and the relative length of each bond
+    let mut neighbors = vec![BTreeMap::new(); atoms.len()];
+    let mut lengths = BTreeMap::new();
+    for (a, b) in close_pairs(atoms, &radii) {
+        let (Some(r_a), Some(r_b)) = (radii[a], radii[b]) else {
+            continue;
+        };
+        let distance = (atoms[a].position - atoms[b].position).norm();
+        if distance <= r_a + r_b + BOND_TOLERANCE {
+            neighbors[a].insert(b, 1usize);
+            neighbors[b].insert(a, 1usize);
+            lengths.insert((a, b), distance / (r_a + r_b));
+        }
+    }
+    let mut free = atoms
+        .iter()
+        .enumerate()
+        .map(|(index, atom)| {
+            let terminal_chalcogens = neighbors[index]
+                .keys()
+                .filter(|neighbor| {
+                    is_chalcogen(atoms[**neighbor].element) && neighbors[**neighbor].len() == 1
+                })
+                .count();
+            let degree = neighbors[index].len();
+            target_valence(atom.element, degree, terminal_chalcogens)
+                .map(|target| target.saturating_sub(degree))
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+    // Number of unsaturated neighbors of each atom, kept up to date as bonds are assigned
+    let mut unsaturated = neighbors
+        .iter()
+        .map(|bonded| {
+            bonded
+                .keys()
+                .filter(|neighbor| free[**neighbor] > 0)
+                .count()
+        })
+        .collect::<Vec<_>>();
+    let is_candidate = |free: &[usize], (a, b): (usize, usize)| free[a] > 0 && free[b] > 0;
+    let is_forced = |free: &[usize], unsaturated: &[usize], (a, b): (usize, usize)| {
+        is_candidate(free, (a, b)) && (unsaturated[a] == 1 || unsaturated[b] == 1)
+    };
+    // Atoms with only one unsaturated neighbor must take the multiple bond,
+    // otherwise the shortest bond is taken
+    let mut forced = lengths
+        .keys()
+        .copied()
+        .filter(|bond| is_forced(&free, &unsaturated, *bond))
+        .collect::<BTreeSet<_>>();
+    let mut by_length = lengths.iter().collect::<Vec<_>>();
+    by_length.sort_by(|(_, x), (_, y)| x.total_cmp(y));
+    // Bonds stop being candidates only, so the shortest one is found by a moving position
+    let mut shortest = 0;
+    loop {
+        while by_length
+            .get(shortest)
+            .is_some_and(|(bond, _)| !is_candidate(&free, **bond))
+        {
+            shortest += 1;
+        }
+        let Some((a, b)) = forced
+            .first()
+            .copied()
+            .or(by_length.get(shortest).map(|(bond, _)| **bond))
+        else {
+            break;
+        };
+        *neighbors[a].get_mut(&b).expect("Bond should exist") += 1;
+        *neighbors[b].get_mut(&a).expect("Bond should exist") += 1;
+        free[a] -= 1;
+        free[b] -= 1;
+        let mut changed = BTreeSet::from([a, b]);
+        for center in [a, b] {
+            if free[center] == 0 {
+                for neighbor in neighbors[center].keys() {
+                    unsaturated[*neighbor] -= 1;
+                    changed.insert(*neighbor);
+                }
+            }
+        }
+        for center in changed {
+            for neighbor in neighbors[center].keys() {
+                let bond = (center.min(*neighbor), center.max(*neighbor));
+                if is_forced(&free, &unsaturated, bond) {
+                    forced.insert(bond);
+                } else {
+                    forced.remove(&bond);
+                }
+            }
+        }
+    }
+    // Octet atoms like N in nitro groups use charge separated forms
+    for (index, atom) in atoms.iter().enumerate() {
+        while obeys_octet(atom.element) && neighbors[index].values().sum::<usize>() > 4 {
+            let Some(terminal) = neighbors[index].iter().find_map(|(neighbor, order)| {
+                (*order > 1 && neighbors[*neighbor].len() == 1).then_some(*neighbor)
+            }) else {
+                break;
+            };
+            *neighbors[index]
+                .get_mut(&terminal)
+                .expect("Bond should exist") -= 1;
+            *neighbors[terminal]
+                .get_mut(&index)
+                .expect("Bond should exist") -= 1;
+        }
+    }
+    let charges = atoms
+        .iter()
+        .enumerate()
+        .map(|(index, atom)| formal_charge(atom.element, neighbors[index].values().sum()))
+        .collect::<Vec<_>>();
+    // Rings are aromatic by the Hückel rule, double bonds in other aromatic rings count as
+    // in the ring for fused rings like naphthalene
+    let rings = small_rings(&neighbors);
+    let mut aromatic_bonds = BTreeSet::new();
+    let mut aromatic_rings = BTreeSet::new();
+    loop {
+        let found = rings
+            .iter()
+            .filter(|ring| !aromatic_rings.contains(*ring))
+            .find(|ring| {
+                let electrons = ring
+                    .iter()
+                    .map(|center| {
+                        let double_in_ring = neighbors[*center].iter().any(|(neighbor, order)| {
+                            *order == 2
+                                && (ring.contains(neighbor)
+                                    || aromatic_bonds
+                                        .contains(&(*center.min(neighbor), *center.max(neighbor))))
+                        });
+                        let double = neighbors[*center].values().any(|order| *order == 2);
+                        pi_electrons(
+                            atoms[*center].element,
+                            charges[*center],
+                            double_in_ring,
+                            double,
+                        )
+                    })
+                    .sum::<Option<usize>>();
+                electrons.is_some_and(|electrons| electrons % 4 == 2)
+                    && ring.iter().all(|center| {
+                        neighbors[*center]
+                            .iter()
+                            .filter(|(neighbor, _)| ring.contains(neighbor))
+                            .all(|(_, order)| *order <= 2)
+                    })
+            })
+            .cloned();
+        let Some(ring) = found else {
+            break;
+        };
+        for a in &ring {
+            for b in neighbors[*a].keys().filter(|b| ring.contains(b) && *b > a) {
+                aromatic_bonds.insert((*a, *b));
+            }
+        }
+        aromatic_rings.insert(ring);
+    }
+    let bonds = neighbors
+        .iter()
+        .enumerate()
+        .flat_map(|(a, bonded)| {
+            let aromatic_bonds = &aromatic_bonds;
+            bonded
+                .iter()
+                .filter(move |(b, _)| **b > a)
+                .map(move |(b, order)| {
+                    if aromatic_bonds.contains(&(a, *b)) {
+                        (a, *b, 1.5)
+                    } else {
+                        (a, *b, *order as f64)
+                    }
+                })
+        })
+        .collect();
+    (bonds, charges)
+}
+
+#[test]
+fn perceive_nitrobenzene() {
+    use nalgebra::Point3;
+    let atoms = [
+        (6, 0., 1.397, 0.),
+        (6, 1.21, 0.6985, 0.),
+        (6, 1.21, -0.6985, 0.),
+        (6, 0., -1.397, 0.),
+        (6, -1.21, -0.6985, 0.),
+        (6, -1.21, 0.6985, 0.),
+        (7, 0., 2.867, 0.),
+        (8, 1.08, 3.47, 0.),
+        (8, -1.08, 3.47, 0.),
+        (1, 2.15, 1.24, 0.),
+        (1, 2.15, -1.24, 0.),
+        (1, 0., -2.48, 0.),
+        (1, -2.15, -1.24, 0.),
+        (1, -2.15, 1.24, 0.),
+    ]
+    .map(|(element, x, y, z)| Atom3D {
+        element,
+        position: Point3::new(x, y, z),
+        ..Default::default()
+    });
+    let (bonds, charges) = perceive_bonds(&atoms);
+    assert_eq!(bonds.len(), 14);
+    assert!(bonds.contains(&(0, 1, 1.5)) && bonds.contains(&(0, 5, 1.5)));
+    assert!(bonds.contains(&(0, 6, 1.)));
+    let no_orders = [7, 8].map(|o| bonds.iter().find(|(a, b, _)| *a == 6 && *b == o).unwrap().2);
+    assert_eq!(no_orders.iter().sum::<f64>(), 3.);
+    assert_eq!(charges[6], 1.);
+    assert_eq!(charges[7] + charges[8], -1.);
+    assert_eq!(charges.iter().sum::<f64>(), 0.);
+}
+
+#[test]
+fn perceive_five_membered_rings() {
+    use nalgebra::Point3;
+    // Regular pentagons of pyrrole and furan, with the heteroatom first
+    let ring = [
+        (0., 1.1824),
+        (-1.1245, 0.3654),
+        (-0.695, -0.9566),
+        (0.695, -0.9566),
+        (1.1245, 0.3654),
+    ];
+    for (heteroatom, hydrogens) in [(7, 0..5), (8, 1..5), (6, 0..5)] {
+        let atoms = ring
+            .iter()
+            .enumerate()
+            .map(|(index, (x, y))| (if index == 0 { heteroatom } else { 6 }, *x, *y))
+            .chain(hydrogens.map(|index| {
+                let (x, y) = ring[index];
+                (1, x * 2.1824 / 1.1824, y * 2.1824 / 1.1824)
+            }))
+            .map(|(element, x, y)| Atom3D {
+                element,
+                position: Point3::new(x, y, 0.),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        let (bonds, _) = perceive_bonds(&atoms);
+        let ring_orders = bonds
+            .iter()
+            .filter(|(a, b, _)| *a < 5 && *b < 5)
+            .map(|(_, _, order)| *order)
+            .collect::<Vec<_>>();
+        assert_eq!(ring_orders.len(), 5);
+        // The cyclopentadienyl radical has a carbon without a double bond or a lone pair
+        let aromatic = heteroatom != 6;
+        assert_eq!(ring_orders.iter().all(|order| *order == 1.5), aromatic);
+    }
+}
+
+#[test]
+fn perceive_water_box() {
+    use nalgebra::Point3;
+    // 1000 water molecules on a grid of 3 Angstrom around the origin
+    let atoms = (0..1000)
+        .flat_map(|index| {
+            let center = Point3::new(
+                (index % 10) as f64 * 3. - 15.,
+                (index / 10 % 10) as f64 * 3. - 15.,
+                (index / 100) as f64 * 3. - 15.,
+            );
+            [(8, 0., 0.), (1, 0.757, 0.586), (1, -0.757, 0.586)].map(|(element, x, y)| Atom3D {
+                element,
+                position: center + nalgebra::Vector3::new(x, y, 0.),
+                ..Default::default()
+            })
+        })
+        .collect::<Vec<_>>();
+    let radii = atoms
+        .iter()
+        .map(|atom| RadiiSet::Covalent.radius(atom.element))
+        .collect::<Vec<_>>();
+    let mut pairs = close_pairs(&atoms, &radii);
+    pairs.sort();
+    let cutoff = 2. * radii.iter().flatten().copied().reduce(f64::max).unwrap() + BOND_TOLERANCE;
+    // Pairs among the first 100 molecules by comparing all of them
+    let expected = (0..300)
+        .flat_map(|a| (a + 1..300).map(move |b| (a, b)))
+        .filter(|(a, b)| (atoms[*a].position - atoms[*b].position).norm() <= cutoff)
+        .collect::<Vec<_>>();
+    assert!(expected
+        .iter()
+        .all(|pair| pairs.binary_search(pair).is_ok()));
+    let (bonds, charges) = perceive_bonds(&atoms);
+    assert_eq!(bonds.len(), 2000);
+    assert!(bonds.iter().all(|(a, b, order)| *order == 1. && b - a <= 2));
+    assert!(charges.iter().all(|charge| *charge == 0.));
+}
@@ -0,0 +1,691 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    chemistry::{element_num_to_symbol, element_symbol_to_num},
+    sparse_molecule::SparseMolecule,
+};
+
+/// Elements written in lowercase in aromatic rings, and uppercase for aliphatic atoms only
+const AROMATIC_ELEMENTS: [usize; 8] = [5, 6, 7, 8, 15, 16, 33, 34];
+
+#[derive(Debug, Clone, PartialEq)]
+enum AtomExpr {
+    Any,
+    Element(usize),
+    /// Element with aromaticity, like `c` and `C`
+    AromaticElement(usize, bool),
+    Aromatic(bool),
+    HydrogenCount(usize),
+    Degree(usize),
+    Charge(i64),
+    Ring(bool),
+    Not(Box<AtomExpr>),
+    And(Vec<AtomExpr>),
+    Or(Vec<AtomExpr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum BondExpr {
+    /// Bonds without symbol, single or aromatic
+    Implicit,
+    Any,
+    Order(f64),
+    Ring,
+    Not(Box<BondExpr>),
+    And(Vec<BondExpr>),
+    Or(Vec<BondExpr>),
+}
+
+/// Properties of atoms and bonds of the molecular graph for matching
+struct Graph {
+    atoms: BTreeMap<usize, GraphAtom>,
+    bonds: BTreeMap<(usize, usize), (f64, bool)>,
+}
+
+struct GraphAtom {
+    element: usize,
+    aromatic: bool,
+    hydrogens: usize,
+    neighbors: Vec<usize>,
+    charge: i64,
+    in_ring: bool,
+}
+
+impl Graph {
+    fn new(molecule: &SparseMolecule) -> Self {
+        let present = (0..molecule.len())
+            .filter_map(|index| Some((index, molecule.atoms.read_atom(index)?)))
+            .filter(|(_, atom)| atom.is_present())
+            .collect::<BTreeMap<_, _>>();
+        let present_bonds = molecule
+            .bonds
+            .iter()
+            .filter(|(a, b, bond)| {
+                *bond != 0. && a < b && present.contains_key(a) && present.contains_key(b)
+            })
+            .collect::<Vec<_>>();
+        let mut adjacency = BTreeMap::<usize, Vec<usize>>::new();
+        for (a, b, _) in &present_bonds {
+            adjacency.entry(*a).or_default().push(*b);
+            adjacency.entry(*b).or_default().push(*a);
+        }
+        // A bond is in a ring unless it is a bridge of the graph
+        let bridges = Self::bridges(&adjacency);
+        let mut bonds = BTreeMap::new();
+        for (a, b, bond) in present_bonds {
+            let in_ring = !bridges.contains(&(a, b));
+            bonds.insert((a, b), (bond, in_ring));
+            bonds.insert((b, a), (bond, in_ring));
+        }
+        let atoms = present
+            .iter()
+            .map(|(index, atom)| {
+                let neighbors = bonds
+                    .range((*index, 0)..=(*index, usize::MAX))
+                    .map(|((_, b), _)| *b)
+                    .collect::<Vec<_>>();
+                let bonded = || {
+                    bonds
+                        .range((*index, 0)..=(*index, usize::MAX))
+                        .map(|(_, bond)| *bond)
+                };
+                let graph_atom = GraphAtom {
+                    element: atom.element,
+                    aromatic: bonded().any(|(order, _)| order == 1.5),
+                    hydrogens: neighbors
+                        .iter()
+                        .filter(|neighbor| present[neighbor].element == 1)
+                        .count(),
+                    charge: atom.formal_charge.round() as i64,
+                    in_ring: bonded().any(|(_, in_ring)| in_ring),
+                    neighbors,
+                };
+                (*index, graph_atom)
+            })
+            .collect();
+        Self { atoms, bonds }
+    }
+
+    /// Bonds whose removal disconnects the graph, by the low links of a depth first search
+    fn bridges(adjacency: &BTreeMap<usize, Vec<usize>>) -> BTreeSet<(usize, usize)> {
+        let mut order = BTreeMap::new();
+        let mut low = BTreeMap::new();
+        let mut bridges = BTreeSet::new();
+        for root in adjacency.keys() {
+            if order.contains_key(root) {
+                continue;
+            }
+            order.insert(*root, order.len());
+            low.insert(*root, order[root]);
+            // Atoms on the search path with their parent and the next neighbor to visit
+            let mut stack = vec![(*root, usize::MAX, 0)];
+            while let Some((atom, parent, position)) = stack.last_mut() {
+                let (atom, parent) = (*atom, *parent);
+                if let Some(next) = adjacency[&atom].get(*position).copied() {
+                    *position += 1;
+                    if next == parent {
+                        continue;
+                    }
+                    if let Some(next_order) = order.get(&next) {
+                        low.insert(atom, low[&atom].min(*next_order));
+                    } else {
+                        order.insert(next, order.len());
+                        low.insert(next, order[&next]);
+                        stack.push((next, atom, 0));
+                    }
+                } else {
+                    stack.pop();
+                    if parent != usize::MAX {
+                        if low[&atom] > order[&parent] {
+                            bridges.insert((atom.min(parent), atom.max(parent)));
+                        }
+                        low.insert(parent, low[&parent].min(low[&atom]));
+                    }
+                }
+            }
+        }
+        bridges
+    }
+}
+
+impl AtomExpr {
+    fn matches(&self, atom: &GraphAtom) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Element(element) => atom.element == *element,
+            Self::AromaticElement(element, aromatic) => {
+                atom.element == *element && atom.aromatic == *aromatic
+            }
+            Self::Aromatic(aromatic) => atom.aromatic == *aromatic,
+            Self::HydrogenCount(count) => atom.hydrogens == *count,
+            Self::Degree(degree) => atom.neighbors.len() == *degree,
+            Self::Charge(charge) => atom.charge == *charge,
+            Self::Ring(in_ring) => atom.in_ring == *in_ring,
+            Self::Not(expr) => !expr.matches(atom),
+            Self::And(exprs) => exprs.iter().all(|expr| expr.matches(atom)),
+            Self::Or(exprs) => exprs.iter().any(|expr| expr.matches(atom)),
+        }
+    }
+}
+
+impl BondExpr {
+    fn matches(&self, order: f64, in_ring: bool) -> bool {
+        match self {
+            Self::Implicit => order == 1. || order == 1.5,
+            Self::Any => true,
+            Self::Order(expected) => order == *expected,
+            Self::Ring => in_ring,
+            Self::Not(expr) => !expr.matches(order, in_ring),
+            Self::And(exprs) => exprs.iter().all(|expr| expr.matches(order, in_ring)),
+            Self::Or(exprs) => exprs.iter().any(|expr| expr.matches(order, in_ring)),
+        }
+    }
+}
+
+/// A SMARTS-like substructure pattern.
+///
+/// Supported are atoms of the organic subset (`C`, `c`, `Cl`, `*`...), bracket atoms with
+/// element symbols, `#n`, `a`, `A`, `Hn`, `Dn`, `Xn`, `R`, `R0`, charges like `+`, `-2` and
+/// atom maps like `:1`, combined by `!`, `&`, `,` and `;`. Bonds are `-`, `=`, `#`, `:`, `~`
+/// and `@` with the same operators. Branches, ring closures and `.` are supported.
+///
+/// Not supported are chirality (`@`, `@@`), isotopes, ring sizes and counts (`rn`, `xn`, `Rn`
+/// other than `R0` only tests ring membership), valence `vn`, implicit hydrogens `hn`,
+/// recursive SMARTS `$(...)`, directional bonds `/` and `\` and component grouping.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    atoms: Vec<(AtomExpr, Option<usize>)>,
+    bonds: Vec<(usize, usize, BondExpr)>,
+}
+
+struct Parser<'a> {
+    chars: Vec<char>,
+    position: usize,
+    source: &'a str,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.position + offset).copied()
+    }
+
+    fn error(&self, message: &str) -> anyhow::Error {
+        anyhow!(
+            "{} at position {} of pattern {}",
+            message,
+            self.position,
+            self.source
+        )
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        let start = self.position;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.position += 1;
+        }
+        self.chars[start..self.position]
+            .iter()
+            .collect::<String>()
+            .parse()
+            .ok()
+    }
+
+    /// Element symbol with exactly the same case, like `Cl` but not `CL`
+    fn element(symbol: &str) -> Option<usize> {
+        element_symbol_to_num(symbol)
+            .filter(|element| element_num_to_symbol(element) == Some(symbol))
+    }
+
+    fn symbol_atom(element: usize, aromatic: bool) -> AtomExpr {
+        if AROMATIC_ELEMENTS.contains(&element) {
+            AtomExpr::AromaticElement(element, aromatic)
+        } else {
+            AtomExpr::Element(element)
+        }
+    }
+
+    fn organic_atom(&mut self) -> Result<AtomExpr> {
+        let c = self.peek().ok_or_else(|| self.error("Atom expected"))?;
+        if c == '*' {
+            self.position += 1;
+            return Ok(AtomExpr::Any);
+        }
+        let two = self.peek_at(1).map(|next| format!("{c}{next}"));
+        for (symbol, element) in [("Cl", 17), ("Br", 35)] {
+            if two.as_deref() == Some(symbol) {
+                self.position += 2;
+                return Ok(AtomExpr::Element(element));
+            }
+        }
+        let element = match c.to_ascii_uppercase() {
+            'B' => 5,
+            'C' => 6,
+            'N' => 7,
+            'O' => 8,
+            'P' => 15,
+            'S' => 16,
+            'F' if c == 'F' => 9,
+            'I' if c == 'I' => 53,
+            _ => Err(self.error("Unknown atom"))?,
+        };
+        self.position += 1;
+        Ok(Self::symbol_atom(element, c.is_ascii_lowercase()))
+    }
+
+    fn atom_primitive(&mut self, first: bool) -> Result<AtomExpr> {
+        let c = self
+            .peek()
+            .ok_or_else(|| self.error("Unclosed bracket atom"))?;
+        let next = self.peek_at(1);
+        // Two letter element symbols take precedence, like `Ru` over `R` and `u`
+        if c.is_ascii_uppercase() {
+            if let Some(element) = next
+                .filter(|next| next.is_ascii_lowercase())
+                .and_then(|next| Self::element(&format!("{c}{next}")))
+            {
+                self.position += 2;
+                return Ok(Self::symbol_atom(element, false));
+            }
+        }
+        if c.is_ascii_lowercase() && next.is_some_and(|next| next.is_ascii_lowercase()) {
+            for (symbol, element) in [("se", 34), ("as", 33)] {
+                if format!("{c}{}", next.unwrap_or_default()) == symbol {
+                    self.position += 2;
+                    return Ok(AtomExpr::AromaticElement(element, true));
+                }
+            }
+        }
+        self.position += 1;
+        let count = |parser: &mut Self| parser.number().unwrap_or(1);
+        Ok(match c {
+            '*' => AtomExpr::Any,
+            '#' => AtomExpr::Element(
+                self.number()
+                    .ok_or_else(|| self.error("Atomic number expected"))?,
+            ),
+            'a' => AtomExpr::Aromatic(true),
+            'A' => AtomExpr::Aromatic(false),
+            // Hydrogen atoms are written as `[H]`, otherwise `H` is the hydrogen count
+            'H' if first => AtomExpr::Element(1),
+            'H' => AtomExpr::HydrogenCount(count(self)),
+            'D' | 'X' => AtomExpr::Degree(count(self)),
+            'R' => AtomExpr::Ring(self.number().unwrap_or(1) > 0),
+            '@' => Err(self.error("Chirality is not supported"))?,
+            '+' | '-' => {
+                let sign = if c == '+' { 1 } else { -1 };
+                let mut magnitude = 1;
+                if let Some(number) = self.number() {
+                    magnitude = number as i64;
+                } else {
+                    while self.peek() == Some(c) {
+                        self.position += 1;
+                        magnitude += 1;
+                    }
+                }
+                AtomExpr::Charge(sign * magnitude)
+            }
+            'b' | 'c' | 'n' | 'o' | 'p' | 's' => {
+                self.position -= 1;
+                self.organic_atom()?
+            }
+            _ => {
+                let element = Self::element(&c.to_string())
+                    .ok_or_else(|| self.error("Unknown atom primitive"))?;
+                Self::symbol_atom(element, false)
+            }
+        })
+    }
+
+    fn atom_unary(&mut self, first: bool) -> Result<AtomExpr> {
+        if self.peek() == Some('!') {
+            self.position += 1;
+            Ok(AtomExpr::Not(Box::new(self.atom_unary(false)?)))
+        } else {
+            self.atom_primitive(first)
+        }
+    }
+
+    fn atom_high_and(&mut self, first: bool) -> Result<AtomExpr> {
+        let mut exprs = vec![self.atom_unary(first)?];
+        while let Some(c) = self.peek() {
+            match c {
+                '&' => self.position += 1,
+                ',' | ';' | ']' | ':' => break,
+                _ => {}
+            }
+            exprs.push(self.atom_unary(false)?);
+        }
+        Ok(if exprs.len() == 1 {
+            exprs.remove(0)
+        } else {
+            AtomExpr::And(exprs)
+        })
+    }
+
+    fn atom_or(&mut self, first: bool) -> Result<AtomExpr> {
+        let mut exprs = vec![self.atom_high_and(first)?];
+        while self.peek() == Some(',') {
+            self.position += 1;
+            exprs.push(self.atom_high_and(false)?);
+        }
+        Ok(if exprs.len() == 1 {
+            exprs.remove(0)
+        } else {
+            AtomExpr::Or(exprs)
+        })
+    }
+
+    fn bracket_atom(&mut self) -> Result<(AtomExpr, Option<usize>)> {
+        // Skip the opening bracket
+        self.position += 1;
+        let mut exprs = vec![self.atom_or(true)?];
+        while self.peek() == Some(';') {
+            self.position += 1;
+            exprs.push(self.atom_or(false)?);
+        }
+        let map = if self.peek() == Some(':') {
+            self.position += 1;
+            Some(
+                self.number()
+                    .ok_or_else(|| self.error("Atom map expected"))?,
+            )
+        } else {
+            None
+        };
+        if self.peek() != Some(']') {
+            Err(self.error("Unclosed bracket atom"))?
+        }
+        self.position += 1;
+        let expr = if exprs.len() == 1 {
+            exprs.remove(0)
+        } else {
+            AtomExpr::And(exprs)
+        };
+        Ok((expr, map))
+    }
+
+    fn is_bond_char(c: char) -> bool {
+        "-=#:~@!&,;".contains(c)
+    }
+
+    fn bond_unary(&mut self) -> Result<BondExpr> {
+        let c = self.peek().ok_or_else(|| self.error("Bond expected"))?;
+        self.position += 1;
+        Ok(match c {
+            '!' => BondExpr::Not(Box::new(self.bond_unary()?)),
+            '-' => BondExpr::Order(1.),
+            '=' => BondExpr::Order(2.),
+            '#' => BondExpr::Order(3.),
+            ':' => BondExpr::Order(1.5),
+            '~' => BondExpr::Any,
+            '@' => BondExpr::Ring,
+            _ => Err(self.error("Unknown bond"))?,
+        })
+    }
+
+    fn bond_expr(&mut self) -> Result<BondExpr> {
+        let mut low = vec![];
+        loop {
+            let mut or = vec![];
+            loop {
+                let mut and = vec![self.bond_unary()?];
+                while let Some(c) = self.peek().filter(|c| Self::is_bond_char(*c)) {
+                    match c {
+                        ',' | ';' => break,
+                        '&' => self.position += 1,
+                        _ => {}
+                    }
+                    and.push(self.bond_unary()?);
+                }
+                or.push(BondExpr::And(and));
+                if self.peek() != Some(',') {
+                    break;
+                }
+                self.position += 1;
+            }
+            low.push(BondExpr::Or(or));
+            if self.peek() != Some(';') {
+                break;
+            }
+            self.position += 1;
+        }
+        Ok(BondExpr::And(low))
+    }
+}
+
+impl Pattern {
+    pub fn parse(source: &str) -> Result<Self> {
+        let mut parser = Parser {
+            chars: source.chars().collect(),
+            position: 0,
+            source,
+        };
+        let mut atoms = vec![];
+        let mut bonds = vec![];
+        let mut previous: Option<usize> = None;
+        let mut branches = vec![];
+        let mut bond = None;
+        let mut rings: BTreeMap<usize, (usize, Option<BondExpr>)> = BTreeMap::new();
+        while let Some(c) = parser.peek() {
+            match c {
+                '(' => {
+                    parser.position += 1;
+                    branches.push(previous);
+                }
+                ')' => {
+                    parser.position += 1;
+                    previous = branches
+                        .pop()
+                        .ok_or_else(|| parser.error("Unmatched branch"))?;
+                }
+                '.' => {
+                    parser.position += 1;
+                    previous = None;
+                }
+                '0'..='9' | '%' => {
+                    let number = if c == '%' {
+                        parser.position += 1;
+                        parser.number()
+                    } else {
+                        parser.position += 1;
+                        c.to_digit(10).map(|digit| digit as usize)
+                    }
+                    .ok_or_else(|| parser.error("Ring closure number expected"))?;
+                    let current =
+                        previous.ok_or_else(|| parser.error("Ring closure without atom"))?;
+                    if let Some((opened, opened_bond)) = rings.remove(&number) {
+                        let expr = bond.take().or(opened_bond).unwrap_or(BondExpr::Implicit);
+                        bonds.push((opened, current, expr));
+                    } else {
+                        rings.insert(number, (current, bond.take()));
+                    }
+                }
+                c if Parser::is_bond_char(c) => {
+                    bond = Some(parser.bond_expr()?);
+                }
+                _ => {
+                    let atom = if c == '[' {
+                        parser.bracket_atom()?
+                    } else {
+                        (parser.organic_atom()?, None)
+                    };
+                    atoms.push(atom);
+                    let current = atoms.len() - 1;
+                    if let Some(previous) = previous {
+                        bonds.push((previous, current, bond.take().unwrap_or(BondExpr::Implicit)));
+                    }
+                    previous = Some(current);
+                }
+            }
+        }
+        if atoms.is_empty() || !rings.is_empty() || !branches.is_empty() || bond.is_some() {
+            Err(anyhow!("Incomplete pattern {}", source))?
+        }
+        Ok(Self { atoms, bonds })
+    }
+
+    /// All matches of the pattern, as the indexes of matched atoms in the order of pattern atoms
+    pub fn matches(&self, molecule: &SparseMolecule) -> Vec<Vec<usize>> {
+        let graph = Graph::new(molecule);
+        let mut results = vec![];
+        self.extend(&graph, &mut vec![], &mut BTreeSet::new(), &mut results);
+        results
+    }
+
+    fn extend(
+        &self,
+        graph: &Graph,
+        mapping: &mut Vec<usize>,
+        used: &mut BTreeSet<usize>,
+        results: &mut Vec<Vec<usize>>,
+    ) {
+        let current = mapping.len();
+        if current == self.atoms.len() {
+            results.push(mapping.clone());
+            return;
+        }
+        let bonds = self
+            .bonds
+            .iter()
+            .filter_map(|(a, b, expr)| match (*a == current, *b == current) {
+                (true, false) if *b < current => Some((*b, expr)),
+                (false, true) if *a < current => Some((*a, expr)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let candidates = match bonds.first() {
+            Some((mapped, _)) => graph.atoms[&mapping[*mapped]].neighbors.clone(),
+            None => graph.atoms.keys().copied().collect(),
+        };
+        for candidate in candidates {
+            if used.contains(&candidate) || !self.atoms[current].0.matches(&graph.atoms[&candidate])
+            {
+                continue;
+            }
+            let bonded = bonds.iter().all(|(mapped, expr)| {
+                graph
+                    .bonds
+                    .get(&(mapping[*mapped], candidate))
+                    .is_some_and(|(order, in_ring)| expr.matches(*order, *in_ring))
+            });
+            if bonded {
+                mapping.push(candidate);
+                used.insert(candidate);
+                self.extend(graph, mapping, used, results);
+                used.remove(&candidate);
+                mapping.pop();
+            }
+        }
+    }
+
+    /// Position of the atom with the map number in the pattern, or the `map`th atom counted
+    /// from 1 if no atom is mapped with the number
+    pub fn mapped_atom(&self, map: usize) -> Option<usize> {
+        self.atoms
+            .iter()
+            .position(|(_, atom_map)| *atom_map == Some(map))
+            .or_else(|| map.checked_sub(1).filter(|index| *index < self.atoms.len()))
+    }
+
+    /// Positions of atoms with map numbers, or all atoms if none is mapped
+    pub fn selected_atoms(&self) -> Vec<usize> {
+        let mapped = (0..self.atoms.len())
+            .filter(|index| self.atoms[*index].1.is_some())
+            .collect::<Vec<_>>();
+        if mapped.is_empty() {
+            (0..self.atoms.len()).collect()
+        } else {
+            mapped
+        }
+    }
+}
+
+#[test]
+fn match_carbonyl_pattern() {
+    use crate::{chemistry::Atom3D, sparse_molecule::SparseAtomList};
+    // Acetic acid with explicit hydrogens
+    let mut molecule = SparseMolecule {
+        atoms: SparseAtomList::from(
+            [6, 6, 8, 8, 1, 1, 1, 1]
+                .map(|element| Atom3D {
+                    element,
+                    ..Default::default()
+                })
+                .to_vec(),
+        ),
+        ..Default::default()
+    };
+    for (a, b, bond) in [
+        (0, 1, 1.),
+        (1, 2, 2.),
+        (1, 3, 1.),
+        (3, 7, 1.),
+        (0, 4, 1.),
+        (0, 5, 1.),
+        (0, 6, 1.),
+    ] {
+        molecule.bonds.set_bond(a, b, Some(bond));
+    }
+    let carbonyl = Pattern::parse("[C:1](=O)[OX2H1]").unwrap();
+    assert_eq!(carbonyl.matches(&molecule), vec![vec![1, 2, 3]]);
+    assert_eq!(carbonyl.selected_atoms(), vec![0]);
+    let methyl = Pattern::parse("[CH3]-[#6;R0]=,:[O,S]").unwrap();
+    assert_eq!(methyl.matches(&molecule).len(), 1);
+    assert_eq!(
+        Pattern::parse("[H]O").unwrap().matches(&molecule),
+        vec![vec![7, 3]]
+    );
+    assert_eq!(Pattern::parse("C~C").unwrap().matches(&molecule).len(), 2);
+    assert!(Pattern::parse("[!c]1ccc1")
+        .unwrap()
+        .matches(&molecule)
+        .is_empty());
+    assert!(Pattern::parse("C(=O").is_err());
+    assert!(Pattern::parse("[Ru+2]").is_ok());
+}
+
+#[test]
+fn ring_bonds_of_fused_rings() {
+    use crate::{chemistry::Atom3D, sparse_molecule::SparseAtomList};
+    // Bicyclo[2.2.0]hexane with an ethyl group, without hydrogens
+    let mut molecule = SparseMolecule {
+        atoms: SparseAtomList::from(
+            [6; 8]
+                .map(|element| Atom3D {
+                    element,
+                    ..Default::default()
+                })
+                .to_vec(),
+        ),
+        ..Default::default()
+    };
+    for (a, b) in [
+        (0, 1),
+        (1, 2),
+        (2, 3),
+        (3, 0),
+        (0, 4),
+        (4, 5),
+        (5, 3),
+        (5, 6),
+        (6, 7),
+    ] {
+        molecule.bonds.set_bond(a, b, Some(1.));
+    }
+    assert_eq!(Pattern::parse("C@C").unwrap().matches(&molecule).len(), 14);
+    assert_eq!(
+        Pattern::parse("[R0]").unwrap().matches(&molecule),
+        vec![vec![6], vec![7]]
+    );
+    assert_eq!(
+        Pattern::parse("[R]!@C").unwrap().matches(&molecule),
+        vec![vec![5, 6]]
+    );
+    assert!(Pattern::parse("[C@@H](C)O").is_err());
+}
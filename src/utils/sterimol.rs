@@ -2,40 +2,66 @@ use anyhow::{Context, Result};
 use petgraph::{csr::IndexType, prelude::StableUnGraph};
 use serde::Deserialize;
 
-use crate::chemistry::Atom3D;
+use crate::{
+    chemistry::{element_num_to_symbol, Atom3D},
+    elements::RadiiSet,
+};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct RadiisItem {
     symbol: String,
     value: f64,
 }
 
+/// Radii from the built-in element table or a user given table
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum Radiis {
+    Builtin(RadiiSet),
+    Table(RadiisTable),
+}
+
+impl Radiis {
+    pub fn get(&self, element: usize) -> Option<f64> {
+        match self {
+            Self::Builtin(radii_set) => radii_set.radius(element),
+            Self::Table(table) => {
+                let symbol = element_num_to_symbol(element)?;
+                table
+                    .iter()
+                    .find(|item| item.symbol == symbol)
+                    .map(|item| item.value)
+            }
+        }
+    }
+}
+
+impl Default for Radiis {
+    fn default() -> Self {
+        Self::Builtin(RadiiSet::default())
+    }
+}
+
 pub fn auto_connect_bonds(
     atoms: &Vec<Atom3D>,
-    r_cov_table: &RadiisTable,
+    r_cov_table: &Radiis,
 ) -> Result<Vec<(usize, usize, f64)>> {
     let mut bonds = vec![];
     for (a_idx, atom) in atoms.iter().enumerate() {
-        let r_a = r_cov_table
-            .get(atom.element)
-            .with_context(|| {
+        let r_a = r_cov_table.get(atom.element).with_context(|| {
+            format!(
+                "Failed to found the radiis for the first atom element {}",
+                atom.element
+            )
+        })?;
+        let p_a = atom.position;
+        for (b_idx, atom) in atoms.iter().enumerate().skip(a_idx + 1) {
+            let r_b = r_cov_table.get(atom.element).with_context(|| {
                 format!(
                     "Failed to found the radiis for the second atom element {}",
                     atom.element
                 )
-            })?
-            .value;
-        let p_a = atom.position;
-        for (b_idx, atom) in atoms.iter().enumerate().skip(a_idx + 1) {
-            let r_b = r_cov_table
-                .get(atom.element)
-                .with_context(|| {
-                    format!(
-                        "Failed to found the radiis for the second atom element {}",
-                        atom.element
-                    )
-                })?
-                .value;
+            })?;
             let distance = (atom.position - p_a).norm();
             if distance <= r_a + r_b {
                 bonds.push((a_idx, b_idx, 1.0))
@@ -60,7 +86,7 @@ pub fn molecular_graph_walk(
         .filter(|neighbor| !excludes.contains(&neighbor.index()))
         .collect::<Vec<_>>();
     if current_depth == limit_depth || neighbors.len() == 0 {
-        Ok(vec![(entry, *current_position)])
+        Ok(vec![(entry, current_position.clone())])
     } else {
         let sub_find_results = neighbors
             .into_iter()
@@ -86,7 +112,7 @@ pub fn get_molecular_graph(
 ) -> MolecularGraph {
     let mut molecular_graph: StableUnGraph<Atom3D, f64, usize> = StableUnGraph::default();
     for atom in atoms {
-        molecular_graph.add_node(*atom);
+        molecular_graph.add_node(atom.clone());
     }
     molecular_graph.extend_with_edges(bonds);
     molecular_graph
@@ -94,7 +120,7 @@ pub fn get_molecular_graph(
 
 pub type RadiisTable = Vec<RadiisItem>;
 
-pub fn sterimol(molecular_graph: &MolecularGraph, table: &RadiisTable) -> Result<(f64, f64, f64)> {
+pub fn sterimol(molecular_graph: &MolecularGraph, table: &Radiis) -> Result<(f64, f64, f64)> {
     let a = molecular_graph
         .node_weight(0.into())
         .with_context(|| "First atom of substituent group not found, require at least 2 atoms")?;
@@ -103,8 +129,7 @@ pub fn sterimol(molecular_graph: &MolecularGraph, table: &RadiisTable) -> Result
         .with_context(|| "Second atom of subsitutent group not found, require at least 2 atoms")?;
     let b_radii = table
         .get(b.element)
-        .with_context(|| format!("Unable to get radii from table for element {}", b.element))?
-        .value;
+        .with_context(|| format!("Unable to get radii from table for element {}", b.element))?;
     let ab = b.position - a.position;
     let axis = ab.normalize();
     let l = molecular_graph
@@ -114,8 +139,7 @@ pub fn sterimol(molecular_graph: &MolecularGraph, table: &RadiisTable) -> Result
         .map(|atom| {
             let radii = table
                 .get(atom.element)
-                .with_context(|| format!("Failed to read radiis of element {}", atom.element))?
-                .value;
+                .with_context(|| format!("Failed to read radiis of element {}", atom.element))?;
             let projection = (atom.position - a.position).dot(&axis);
             Ok::<f64, anyhow::Error>(projection + radii)
         })
@@ -131,12 +155,9 @@ pub fn sterimol(molecular_graph: &MolecularGraph, table: &RadiisTable) -> Result
                     .into_iter()
                     .map(|(_, atom)| atom)
                     .map(|atom| {
-                        let c_radii = table
-                            .get(atom.element)
-                            .with_context(|| {
-                                format!("Failed to read radiis of element {}", atom.element)
-                            })?
-                            .value;
+                        let c_radii = table.get(atom.element).with_context(|| {
+                            format!("Failed to read radiis of element {}", atom.element)
+                        })?;
                         let bc = atom.position - b.position;
                         let projection = bc.dot(&axis) * axis;
                         let distance = (bc - projection).norm();
@@ -201,3 +222,14 @@ pub fn tolman_cone_angle(molecular_graph: &MolecularGraph) -> Result<f64> {
         Ok(tolman_angle / (branches as f64) * 2.)
     }
 }
+
+#[test]
+fn radiis_table_by_symbol() {
+    let table: Radiis =
+        serde_json::from_str(r#"[{"symbol": "H", "value": 0.31}, {"symbol": "C", "value": 0.76}]"#)
+            .unwrap();
+    assert_eq!(table.get(6), Some(0.76));
+    assert_eq!(table.get(2), None);
+    let builtin: Radiis = serde_json::from_str(r#""bondi""#).unwrap();
+    assert_eq!(builtin.get(7), Some(1.55));
+}
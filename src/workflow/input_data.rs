@@ -1,6 +1,7 @@
 use std::collections::BTreeMap;
 use std::path::PathBuf;
 
+use crate::elements::ElementOverride;
 use crate::sparse_molecule::SparseMolecule;
 use serde::{Deserialize, Serialize};
 
@@ -13,6 +14,9 @@ pub struct WorkflowInput {
     pub binaries: Vec<PathBuf>,
     #[serde(default)]
     pub base: SparseMolecule,
+    /// Override properties in the built-in element table, keyed by element symbols
+    #[serde(default)]
+    pub elements: BTreeMap<String, ElementOverride>,
     pub steps: Steps,
 }
 
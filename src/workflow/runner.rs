@@ -1,6 +1,7 @@
 use crate::{
+    descriptors::Descriptors,
     external::{obabel::obabel, regexsed::regex_sed},
-    io::{BasicIOMolecule, NamespaceMapping},
+    io::{BasicIOMolecule, Frame, GaussianOptions, NamespaceMapping, OrcaOptions},
     layer::{Layer, SelectOne},
     layer::{LayerStorageError, SelectMany},
     sparse_molecule::SparseMolecule,
@@ -9,7 +10,6 @@ use crate::{
 use anyhow::{anyhow, Context, Result};
 use cached::{proc_macro::cached, SizedCache};
 use fancy_regex::Regex;
-use nalgebra::Vector3;
 use std::collections::BTreeSet;
 use std::fs::File;
 use std::path::PathBuf;
@@ -56,8 +56,11 @@ impl RenameOptions {
 #[derive(Deserialize, Debug)]
 pub struct FormatOptions {
     format: String,
+    /// Lines before the content, `{charge}` and `{multiplicity}` are replaced with
+    /// values of the structure
     #[serde(default)]
     prefix: String,
+    /// Lines after the content, with the same placeholders as `prefix`
     #[serde(default)]
     suffix: String,
     #[serde(default)]
@@ -66,6 +69,26 @@ pub struct FormatOptions {
     regex: Vec<String>,
     #[serde(default)]
     export_map: bool,
+    /// Write hidden atoms as ghost atoms instead of dropping them, `post_file` must then have
+    /// the ghost atoms too
+    #[serde(default)]
+    ghost_hidden: bool,
+    /// Link 0 commands, route section, constraints and following jobs of the `gaussian` format
+    #[serde(default)]
+    gaussian: GaussianOptions,
+    /// Keywords, input blocks and constraints of the `orca` format
+    #[serde(default)]
+    orca: OrcaOptions,
+}
+
+/// The format and filename of the calculation result, with an optional frame for files with
+/// multiple structures, like `[gaussian, job.log]`, `[xyz, crest_conformers.xyz, all]` or
+/// `[xyz, xtbopt.log, lowest_energy]`
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum PostFile {
+    Frame(String, String, Frame),
+    Last(String, String),
 }
 
 #[derive(Deserialize, Debug)]
@@ -200,9 +223,10 @@ pub enum Runner {
         /// The output file format and filename
         /// 
         /// like `[xyz, output.xyz]`, ignore if the calculation result
-        /// should not be used to update the structure.
+        /// should not be used to update the structure. The last structure is read from
+        /// files with multiple structures, unless a frame is given like `[gaussian, job.log, 0]`.
         #[serde(default)]
-        post_file: Option<(String, String)>,
+        post_file: Option<PostFile>,
         /// Continue even if some calculation failed, default to false which means if one 
         /// structure calculation failed, the LME will abort the following task. 
         /// 
@@ -217,6 +241,14 @@ pub enum Runner {
         #[serde(default)]
         stderr: Option<String>,
     },
+    #[doc = include_str!("docs/Descriptors.md")]
+    Descriptors {
+        /// The output file, in JSON format if the extension is `.json`, otherwise in CSV format
+        path: PathBuf,
+        /// Selections to compute descriptors for, the whole molecule is used if omitted
+        #[serde(default)]
+        selects: BTreeMap<String, SelectMany>,
+    },
     /// Do nothing but leave a checkpoint if the `name` field is set in the steps
     #[default]
     CheckPoint,
@@ -252,7 +284,11 @@ impl Runner {
                 Ok(RunnerOutput::SingleWindow(current_window))
             }
             Self::AppendLayers { layers } => {
-                let layer_ids = layer_storage.create_layers(layers);
+                let layers = layers
+                    .iter()
+                    .map(|layer| layer.clone().with_base(base))
+                    .collect::<Vec<_>>();
+                let layer_ids = layer_storage.create_layers(&layers);
                 Ok(RunnerOutput::SingleWindow(
                     current_window
                         .into_iter()
@@ -265,7 +301,10 @@ impl Runner {
                 ))
             }
             Self::DistributeLayers(maps) => {
-                let new_layers = maps.values().cloned().collect::<Vec<_>>();
+                let new_layers = maps
+                    .values()
+                    .map(|layer| layer.clone().with_base(base))
+                    .collect::<Vec<_>>();
                 let new_layers = layer_storage.create_layers(&new_layers).collect::<Vec<_>>();
                 let new_layers = maps
                     .keys()
@@ -381,10 +420,24 @@ impl Runner {
                     }
                     // Prepare the input file for external program
                     let structure = cached_read_stack(base, &layer_storage, stack_path)?;
-                    let bonds = structure.bonds.clone().to_continuous_list(&structure.atoms);
-                    let atoms = structure.atoms.clone().into();
-                    let basic_molecule = BasicIOMolecule::new(title.to_string(), atoms, bonds);
-                    let pre_content = basic_molecule.output(&pre_format.format)?;
+                    let bonds = structure.bonds.to_continuous_list_by(
+                        &structure.atoms.continuous_indexes(pre_format.ghost_hidden),
+                    );
+                    let atoms = structure.atoms.to_continuous_atoms(pre_format.ghost_hidden);
+                    let mut basic_molecule = BasicIOMolecule::new(title.to_string(), atoms, bonds);
+                    basic_molecule.lattice = structure.lattice;
+                    let (charge, multiplicity) =
+                        (structure.total_charge(), structure.spin_multiplicity());
+                    basic_molecule.charge = Some(charge);
+                    basic_molecule.multiplicity = Some(multiplicity);
+                    // Ids of exported atoms are used by constraints
+                    basic_molecule.ids =
+                        NamespaceMapping::new(structure.clone(), pre_format.ghost_hidden).ids;
+                    let pre_content = match pre_format.format.as_str() {
+                        "gaussian" => basic_molecule.output_to_gaussian(&pre_format.gaussian)?,
+                        "orca" => basic_molecule.output_to_orca(&pre_format.orca)?,
+                        format => basic_molecule.output(format)?,
+                    };
                     let pre_content = if pre_format.openbabel {
                         obabel(
                             &pre_content,
@@ -398,11 +451,18 @@ impl Runner {
                     };
                     let mut pre_content = regex_sed(&pre_content, &pre_format.regex.join("; "))?;
 
+                    let fill_charge = |template: &str| {
+                        template
+                            .replace("{charge}", &charge.to_string())
+                            .replace("{multiplicity}", &multiplicity.to_string())
+                    };
                     if pre_format.prefix.len() > 0 {
-                        pre_content = format!("{}\n{}", pre_format.prefix, pre_content)
+                        pre_content =
+                            format!("{}\n{}", fill_charge(&pre_format.prefix), pre_content)
                     }
                     if pre_format.suffix.len() > 0 {
-                        pre_content = format!("{}\n{}", pre_content, pre_format.suffix)
+                        pre_content =
+                            format!("{}\n{}", pre_content, fill_charge(&pre_format.suffix))
                     }
 
                     let pre_path = working_directory.join(pre_filename);
@@ -423,7 +483,8 @@ impl Runner {
                     if pre_format.export_map {
                         let mut map_file_path = working_directory.join(&pre_filename);
                         map_file_path.set_extension("map.json");
-                        let content = NamespaceMapping::from(structure.clone());
+                        let content =
+                            NamespaceMapping::new(structure.clone(), pre_format.ghost_hidden);
                         let file = File::create(&map_file_path).with_context(|| {
                             format!("Unable to create map file at {:?}", map_file_path)
                         })?;
@@ -440,6 +501,8 @@ impl Runner {
                         command
                             .current_dir(&working_directory)
                             .args(args)
+                            .env("LME_CHARGE", charge.to_string())
+                            .env("LME_MULTIPLICITY", multiplicity.to_string())
                             .envs(envs);
                         if *stdin {
                             let stdin = Stdio::from(File::open(&pre_path).with_context(|| {
@@ -488,7 +551,13 @@ impl Runner {
                                 result.code()
                             ))?;
                         }
-                        if let Some((post_format, post_filename)) = post_file {
+                        if let Some(post_file) = post_file {
+                            let (post_format, post_filename, frame) = match post_file {
+                                PostFile::Frame(format, filename, frame) => {
+                                    (format, filename, *frame)
+                                }
+                                PostFile::Last(format, filename) => (format, filename, Frame::Last),
+                            };
                             let post_path = working_directory.join(post_filename);
                             let post_file = File::open(&post_path).with_context(|| {
                                 format!(
@@ -496,60 +565,113 @@ impl Runner {
                                     post_path, title
                                 )
                             })?;
-                            let post_content = BasicIOMolecule::input(&post_format, post_file)?;
-                            let updated_atoms = structure
-                                .atoms
-                                .update_from_continuous_list(&post_content.atoms)
-                                .with_context(|| {
-                                    format!(
-                                        "Failed to import atoms from calculated result for structure {}",
-                                        title
-                                    )
-                                })?;
-                            let updated_bonds = post_content
-                                .bonds
+                            // Every frame becomes a structure titled like `title_1` with `all`
+                            let post_contents = if frame == Frame::All {
+                                BasicIOMolecule::input_all(post_format, post_file)?
+                            } else {
+                                vec![BasicIOMolecule::input_frame(post_format, post_file, frame)?]
+                            };
+                            post_contents
                                 .into_iter()
-                                .map(|(a, b, bond)| {
-                                    Some((
-                                        structure.atoms.from_continuous_index(a)?,
-                                        structure.atoms.from_continuous_index(b)?,
-                                        bond,
-                                    ))
+                                .enumerate()
+                                .map(|(index, post_content)| {
+                                    let title = if frame == Frame::All {
+                                        format!("{}_{}", title, index + 1)
+                                    } else {
+                                        title.to_string()
+                                    };
+                                    // Indexes of the exported atoms, with the ghost atoms if written
+                                    let sparse_indexes = structure
+                                        .atoms
+                                        .continuous_indexes(pre_format.ghost_hidden)
+                                        .into_keys()
+                                        .collect::<Vec<_>>();
+                                    let updated_atoms = structure
+                                        .atoms
+                                        .update_from_continuous_list(
+                                            &post_content.atoms,
+                                            pre_format.ghost_hidden,
+                                        )
+                                        .with_context(|| {
+                                            format!(
+                                                "Failed to import atoms from calculated result for structure {}, {} atoms exported but {} read",
+                                                title,
+                                                sparse_indexes.len(),
+                                                post_content.atoms.len()
+                                            )
+                                        })?;
+                                    let updated_bonds = post_content
+                                        .bonds
+                                        .into_iter()
+                                        .map(|(a, b, bond)| {
+                                            Some((
+                                                *sparse_indexes.get(a)?,
+                                                *sparse_indexes.get(b)?,
+                                                bond,
+                                            ))
+                                        })
+                                        .collect::<Option<Vec<_>>>()
+                                        .with_context(|| {
+                                            format!(
+                                                "Failed to import bonds from calculated results for structure {}",
+                                                title
+                                            )
+                                        })?;
+                                    let mut structure = SparseMolecule::default();
+                                    structure.extend_to(structure.len());
+                                    structure.atoms.migrate(updated_atoms);
+                                    for (a, b, bond) in updated_bonds {
+                                        structure.bonds.set_bond(a, b, Some(bond));
+                                    }
+                                    structure.lattice = post_content.lattice;
+                                    structure.charge = post_content.charge;
+                                    structure.multiplicity = post_content.multiplicity;
+                                    structure.properties = post_content.properties;
+                                    Ok((title, stack_path, structure))
                                 })
-                                .collect::<Option<Vec<_>>>()
-                                .with_context(|| {
-                                    format!(
-                                        "Failed to import bonds from calculated results for structure {}",
-                                        title
-                                    )
-                                })?;
-                            let mut structure = SparseMolecule::default();
-                            structure.extend_to(structure.len());
-                            structure.atoms.migrate(updated_atoms);
-                            for (a, b, bond) in updated_bonds {
-                                structure.bonds.set_bond(a, b, Some(bond));
-                            }
-                            Ok::<_, anyhow::Error>((title, stack_path, structure))
+                                .collect::<Result<Vec<_>>>()
                         } else {
-                            Ok((title, stack_path, SparseMolecule::default()))
+                            Ok(vec![(
+                                title.to_string(),
+                                stack_path,
+                                SparseMolecule::default(),
+                            )])
                         }
                     } else {
-                        Ok((title, stack_path, SparseMolecule::default()))
+                        Ok(vec![(
+                            title.to_string(),
+                            stack_path,
+                            SparseMolecule::default(),
+                        )])
                     }
                 };
                 let results = if *serial_mode {
                     let outputs = current_window.iter().map(handler);
                     if *ignore_failed {
-                        outputs.filter_map(|item| item.ok()).collect::<Vec<_>>()
+                        outputs
+                            .filter_map(|item| item.ok())
+                            .flatten()
+                            .collect::<Vec<_>>()
                     } else {
-                        outputs.collect::<Result<Vec<_>>>()?
+                        outputs
+                            .collect::<Result<Vec<_>>>()?
+                            .into_iter()
+                            .flatten()
+                            .collect()
                     }
                 } else {
                     let outputs = current_window.par_iter().map(handler);
                     if *ignore_failed {
-                        outputs.filter_map(|item| item.ok()).collect::<Vec<_>>()
+                        outputs
+                            .filter_map(|item| item.ok())
+                            .flatten()
+                            .collect::<Vec<_>>()
                     } else {
-                        outputs.collect::<Result<Vec<_>>>()?
+                        outputs
+                            .collect::<Result<Vec<_>>>()?
+                            .into_iter()
+                            .flatten()
+                            .collect()
                     }
                 };
                 // Receive the execution result
@@ -560,7 +682,7 @@ impl Runner {
                             layer_storage.create_layers(&[Layer::Fill { data: updated }]);
                         let mut stack_path = stack_path.clone();
                         stack_path.extend(updated_layer);
-                        window.insert(title.to_string(), stack_path);
+                        window.insert(title, stack_path);
                     }
                     Ok(RunnerOutput::SingleWindow(window))
                 } else {
@@ -600,66 +722,23 @@ impl Runner {
 
                 let mut result = BTreeMap::new();
                 for (substituent_name, substituent) in substituents {
-                    let replace_atom =
-                        SelectOne::Index(1)
-                            .get_atom(&substituent)
-                            .with_context(|| {
-                                format!(
-                                    "Substituent must have at least 2 atoms, substituent title: {}",
-                                    substituent_name
-                                )
-                            })?;
+                    if substituent.len() < 2 {
+                        Err(anyhow!(
+                            "Substituent must have at least 2 atoms, substituent title: {}",
+                            substituent_name
+                        ))?
+                    }
                     let mut updated_stacks = BTreeMap::new();
                     for (current_title, stack_path) in current_window {
                         let title = format!("{}_{}", current_title, substituent_name);
                         let mut stack_path = stack_path.clone();
                         for (g_name, (center, replace)) in address {
-                            let current_structure =
-                                cached_read_stack(base, &layer_storage, &stack_path)?;
-                            let center_layer = Layer::SetCenter {
-                                select: center.clone(),
-                                center: Default::default(),
-                            };
-                            let align_layer = Layer::DirectionAlign {
-                                select: replace.clone(),
-                                direction: Vector3::x(),
-                            };
-                            let align_layers =
-                                layer_storage.create_layers(&[center_layer, align_layer]);
-                            let mut substituent = substituent.clone();
-                            SelectOne::Index(0).set_atom(&mut substituent, None);
-                            SelectOne::Index(1).set_atom(&mut substituent, None);
-                            let substituent = Layer::GroupMap {
-                                groups: vec![(g_name.to_string(), SelectMany::All)],
-                            }
-                            .filter(substituent)
-                            .expect("SelectOne error will never happend at substituent rename");
-                            let offset = current_structure.atoms.len();
-                            let mut substituent = substituent.offset(offset);
-                            substituent.ids = current_structure.ids.clone();
-                            replace
-                                .set_atom(&mut substituent, Some(replace_atom))
-                                .with_context(|| {
-                                    format!(
-                                        "The replace selector {:?} in {:?} is not validated",
-                                        replace, substituent
-                                    )
-                                })?;
-                            let replaced_index = replace.to_index(&substituent).unwrap();
-                            let updated_bonds = substituent
-                                .bonds
-                                .get_neighbors(offset + 1)
-                                .unwrap()
-                                .enumerate()
-                                .map(|(index, bond)| (replaced_index, index, bond.clone()))
-                                .collect::<Vec<_>>();
-                            for (a, b, bond) in updated_bonds {
-                                substituent.bonds.set_bond(a, b, bond);
-                            }
-                            stack_path.extend(align_layers);
-                            stack_path.extend(
-                                layer_storage.create_layers(&[Layer::Fill { data: substituent }]),
-                            );
+                            stack_path.extend(layer_storage.create_layers(&[Layer::Substitute {
+                                center: center.clone(),
+                                replace: replace.clone(),
+                                name: g_name.to_string(),
+                                data: substituent.clone(),
+                            }]));
                         }
                         updated_stacks.insert(title, stack_path);
                     }
@@ -676,6 +755,35 @@ impl Runner {
                     })
                     .collect::<Result<BTreeMap<_, _>>>()?,
             )),
+            Self::Descriptors { path, selects } => {
+                let selects = if selects.is_empty() {
+                    BTreeMap::from([("molecule".to_string(), SelectMany::All)])
+                } else {
+                    selects.clone()
+                };
+                let table = current_window
+                    .par_iter()
+                    .map(|(title, stack_path)| {
+                        let structure = cached_read_stack(base, layer_storage, stack_path)?;
+                        let descriptors = selects
+                            .iter()
+                            .map(|(name, select)| (name.to_string(), structure.descriptors(select)))
+                            .collect::<BTreeMap<_, _>>();
+                        Ok((title.to_string(), descriptors))
+                    })
+                    .collect::<Result<BTreeMap<_, _>>>()?;
+                let file = File::create(path)
+                    .with_context(|| format!("Unable to create descriptors file {:?}", path))?;
+                if path
+                    .extension()
+                    .is_some_and(|extension| extension == "json")
+                {
+                    serde_json::to_writer_pretty(file, &table)?;
+                } else {
+                    write_descriptors_csv(file, &selects, &table)?;
+                }
+                Ok(RunnerOutput::None)
+            }
             Self::Break { filepath } => {
                 if std::fs::exists(filepath)? {
                     Ok(RunnerOutput::None)
@@ -690,6 +798,52 @@ impl Runner {
     }
 }
 
+fn write_descriptors_csv<W: Write>(
+    mut w: W,
+    selects: &BTreeMap<String, SelectMany>,
+    table: &BTreeMap<String, BTreeMap<String, Descriptors>>,
+) -> Result<()> {
+    let columns = [
+        "formula",
+        "atoms",
+        "mass",
+        "centroid_x",
+        "centroid_y",
+        "centroid_z",
+        "com_x",
+        "com_y",
+        "com_z",
+        "moment_a",
+        "moment_b",
+        "moment_c",
+    ];
+    let header = selects
+        .keys()
+        .flat_map(|name| columns.map(|column| format!("{name}.{column}")));
+    let header = std::iter::once("title".to_string()).chain(header);
+    writeln!(w, "{}", header.collect::<Vec<_>>().join(","))?;
+    let optional = |values: Option<[f64; 3]>| {
+        values
+            .map(|values| values.map(|value| value.to_string()))
+            .unwrap_or_default()
+    };
+    for (title, descriptors) in table {
+        let mut row = vec![format!("\"{}\"", title.replace('"', "\"\""))];
+        for descriptors in descriptors.values() {
+            row.extend([
+                descriptors.formula.to_string(),
+                descriptors.atoms.to_string(),
+                descriptors.mass.to_string(),
+            ]);
+            row.extend(optional(descriptors.centroid));
+            row.extend(optional(descriptors.center_of_mass));
+            row.extend(optional(descriptors.principal_moments));
+        }
+        writeln!(w, "{}", row.join(","))?;
+    }
+    Ok(())
+}
+
 /// In a workflow, the base and existed layers will not be modified or deleted,
 /// so the result of read_stack function is in fact only dependent on the path
 /// parameter so create a cached function here is reasonable.